@@ -1,20 +1,134 @@
 //! Describes the command-line interface
 
-use clap::Parser;
+use std::path::PathBuf;
 
-use crate::formatter::OutputFormat;
-use crate::helpers;
+use clap::{Parser, Subcommand};
+
+use fstree::columns::Column;
+use fstree::config::ConfigBuilder;
+use fstree::diff::CompareBy;
+use fstree::formatter::{Charset, OutputFormat};
+use fstree::hash::HashAlgorithm;
+use fstree::{FileKind, GroupDirs, PermMatch, PointInTime, SizeFormat, SortKey, TimeStyle};
 
 /// Command line arguments for the fstree utility
 ///
 /// This struct holds the configuration options that can be passed
 /// to the program through command line arguments.
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(author, version, about, args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// The directory path to generate the tree from
-    pub root: Option<std::path::PathBuf>,
+    pub root: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub options: Options,
+}
+
+/// The subcommands, which do something else than showing a single tree
+///
+/// A directory with the same name as a subcommand can still be shown as `./name`.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Save a snapshot of the tree, with its metadata (and hashes, with --hash), to a JSON file
+    Snapshot {
+        /// The file to write the snapshot to
+        #[clap(short, long)]
+        output: PathBuf,
+
+        /// The directory path to take the snapshot of
+        root: Option<PathBuf>,
+
+        #[command(flatten)]
+        options: Options,
+    },
+    /// Show the differences between two snapshots, or between a snapshot and a directory
+    Diff {
+        /// The snapshot (or directory) to compare from
+        old: PathBuf,
+
+        /// The snapshot (or directory) to compare to
+        new: PathBuf,
+
+        #[command(flatten)]
+        options: Options,
+    },
+    /// Compare two directories that should be the same, e.g. build outputs and a staging directory
+    Compare {
+        /// The directory on the left
+        left: PathBuf,
+
+        /// The directory on the right
+        right: PathBuf,
+
+        /// What to compare the files by: size, mtime and/or hash
+        #[clap(long, value_delimiter = ',', default_value = "size,mtime")]
+        by: Vec<CompareBy>,
+
+        /// Show the two trees next to each other, for wide terminals
+        #[clap(long)]
+        side_by_side: bool,
+
+        #[command(flatten)]
+        options: Options,
+    },
+    /// Find the files with identical contents, and how much space removing the copies frees
+    Dupes {
+        /// The directory path to search for duplicates
+        root: Option<PathBuf>,
+
+        #[command(flatten)]
+        options: Options,
+    },
+    /// Create the directories and empty files of a tree diagram (or of a JSON snapshot)
+    Scaffold {
+        /// The file with the tree diagram, as printed by fstree, or a snapshot
+        layout: PathBuf,
+
+        /// The directory to create the entries in, which stands for the root of the diagram
+        #[clap(long, value_name = "DIR")]
+        into: PathBuf,
+
+        /// Only show what would be created
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+
+        /// Overwrite the existing files (existing directories are always kept)
+        #[clap(long)]
+        force: bool,
+
+        #[command(flatten)]
+        options: DiagramOptions,
+    },
+}
+
+/// The options that describe how the branches of a tree diagram are drawn, for reading
+/// one back with `scaffold`
+#[derive(clap::Args, Debug)]
+pub struct DiagramOptions {
+    /// The prefix string used for each level of the tree
+    #[clap(short, long)]
+    pub prefix: Option<String>,
+
+    /// The prefix string used for the last entry of each branch
+    #[clap(short, long)]
+    pub last_prefix: Option<String>,
+
+    /// The prefix string used for the children of a branch
+    #[clap(short, long)]
+    pub child_prefix: Option<String>,
+
+    /// The characters the tree is drawn with by default: utf8 or ascii
+    #[clap(long)]
+    pub charset: Option<Charset>,
+}
 
+/// The options that control which entries are shown, and how
+#[derive(clap::Args, Debug)]
+pub struct Options {
     /// Show full path for each file entry
     #[clap(short, long)]
     pub full_path: bool,
@@ -30,17 +144,117 @@ pub struct Args {
     #[clap(short, long)]
     pub child_prefix: Option<String>,
 
-    /// Show all files and directories, including hidden files
+    /// The characters to draw the tree with by default: utf8 or ascii
+    #[clap(long)]
+    pub charset: Option<Charset>,
+
+    /// Show all files and directories, including hidden and ignored files (same as --hidden --no-ignore)
     #[clap(short = 'a', long, alias = "all")]
     pub show_all: bool,
 
-    /// Show only files that match the pattern (glob syntax)
+    /// Show hidden files and directories (those whose name starts with a dot)
+    #[clap(short = 'H', long)]
+    pub hidden: bool,
+
+    /// Show the files matched by ignore files (.gitignore, global excludes and custom ignore files)
+    #[clap(long)]
+    pub no_ignore: bool,
+
+    /// Show the files matched by git's ignore files, but still honor the custom ignore files
+    #[clap(long)]
+    pub no_ignore_vcs: bool,
+
+    /// Do not read the .gitignore files of the directories above the root
+    #[clap(long)]
+    pub no_ignore_parent: bool,
+
+    /// Do not read git's global excludes file (core.excludesFile)
+    #[clap(long)]
+    pub no_ignore_global: bool,
+
+    /// Show only files that match the pattern (glob syntax, repeatable, `!` negates)
     #[clap(short, long, alias = "pattern")]
-    pub include: Option<String>,
+    pub include: Option<Vec<String>>,
 
-    /// Exclude files that match the pattern (glob syntax)
+    /// Exclude files that match the pattern (glob syntax, repeatable, `!` negates)
     #[clap(short, long)]
-    pub exclude: Option<String>,
+    pub exclude: Option<Vec<String>>,
+
+    /// Read more include patterns from a file, one per line (`#` starts a comment)
+    #[clap(long)]
+    pub pattern_file: Option<std::path::PathBuf>,
+
+    /// Let the exclude patterns hide whole directories too
+    #[clap(long)]
+    pub exclude_dirs: bool,
+
+    /// Show only files that match the regular expression (repeatable)
+    #[clap(long)]
+    pub regex_include: Option<Vec<String>>,
+
+    /// Exclude files that match the regular expression (repeatable)
+    #[clap(long)]
+    pub regex_exclude: Option<Vec<String>>,
+
+    /// Match the regular expressions against the path relative to the root, instead of the name
+    #[clap(long)]
+    pub regex_path: bool,
+
+    /// Make the regular expressions case-insensitive
+    #[clap(long)]
+    pub regex_ignore_case: bool,
+
+    /// Show only entries of these types: f (file), d (directory), l (symlink), x (executable), p (fifo), s (socket)
+    #[clap(short = 't', long = "type", value_delimiter = ',')]
+    pub types: Option<Vec<FileKind>>,
+
+    /// Show only files of at least this size, e.g. 10k or 1.5MB
+    #[clap(long, value_parser = fstree::parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Show only files of at most this size, e.g. 10k or 1.5MB
+    #[clap(long, value_parser = fstree::parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Show only files modified after this: a duration ago (e.g. 2d, 1h30m), a date or a reference file
+    #[clap(long)]
+    pub newer: Option<PointInTime>,
+
+    /// Show only files modified before this: a duration ago (e.g. 2d, 1h30m), a date or a reference file
+    #[clap(long)]
+    pub older: Option<PointInTime>,
+
+    /// Show only files whose permissions are exactly (644), at least (-644) or any of (/111) these bits
+    #[clap(long, allow_hyphen_values = true)]
+    pub perm: Option<PermMatch>,
+
+    /// Show only files owned by this user (name or ID)
+    #[clap(long)]
+    pub user: Option<String>,
+
+    /// Show only files owned by this group (name or ID)
+    #[clap(long)]
+    pub group: Option<String>,
+
+    /// Show only files matching an expression, e.g. "(ext == rs and size > 10KB) or name ~ 'README*'"
+    #[clap(long = "where", value_name = "EXPR")]
+    pub expression: Option<String>,
+
+    /// Show only files whose contents match the regular expression
+    #[clap(long, value_name = "REGEX")]
+    pub contains: Option<String>,
+
+    /// Search binary files too with --contains
+    #[clap(long)]
+    pub binary: bool,
+
+    /// Show the number of matches of --contains next to each file
+    #[clap(long)]
+    pub count_matches: bool,
+
+    /// Hide the directories that contain no files, e.g. after filtering with --include
+    #[clap(long)]
+    pub prune: bool,
 
     /// Custom ignore files
     #[clap(long, alias = "ignore-file")]
@@ -60,12 +274,51 @@ pub struct Args {
 
     /// The format to use for the filesize. e.g. Bytes (B), KiloBytes (KB), MegaBytes (MB), GigaBytes (GB) etc.
     #[clap(long)]
-    pub size_format: Option<helpers::bytes::Format>,
+    pub size_format: Option<SizeFormat>,
+
+    /// Show the space used on disk instead of the apparent size
+    #[clap(long, alias = "du")]
+    pub disk_usage: bool,
+
+    /// Show the git status of each entry, like `git status --short`
+    #[clap(long, alias = "git")]
+    pub git_status: bool,
+
+    /// Show only the files that differ from a git revision (HEAD by default), given with an equals
+    /// sign, e.g. --changed=main
+    #[clap(
+        long,
+        value_name = "REF",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "HEAD"
+    )]
+    pub changed: Option<String>,
+
+    /// Compare with the merge base of the --changed revision and HEAD, e.g. where a branch forked
+    #[clap(long, requires = "changed")]
+    pub merge_base: bool,
+
+    /// Show the tree of a git revision (any tree-ish, e.g. v1.2.0) instead of the files on disk
+    #[clap(long, value_name = "REV", conflicts_with_all = ["changed", "git_status"])]
+    pub rev: Option<String>,
 
     /// The maximum depth to recurse
     #[clap(short = 'd', long, aliases = ["depth", "level"])]
     pub max_depth: Option<usize>,
 
+    /// Sort the entries of each directory by: name, size, mtime, extension, type, commit or none
+    #[clap(long)]
+    pub sort: Option<SortKey>,
+
+    /// Where to place directories among the other entries: first, last or mixed
+    #[clap(long)]
+    pub group_dirs: Option<GroupDirs>,
+
+    /// Reverse the sort order
+    #[clap(long)]
+    pub reverse: bool,
+
     /// The output format to use (text, json, xml)
     #[clap(long)]
     pub format: Option<OutputFormat>,
@@ -74,6 +327,30 @@ pub struct Args {
     #[clap(long, alias = "plain")]
     pub no_color: bool,
 
+    /// The number of threads to use when walking the directory tree (defaults to the number of CPUs)
+    #[clap(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Collect file metadata (permissions, owner, timestamps, inode, etc.), e.g. for the JSON output
+    #[clap(short, long)]
+    pub metadata: bool,
+
+    /// The columns of file details to show before the tree, in order (perms, user, group, size, mtime, links, inode, git, change, commit-date, commit, author, subject, diff, side, digest)
+    #[clap(long, value_delimiter = ',')]
+    pub columns: Option<Vec<Column>>,
+
+    /// How to show timestamps in the columns: iso, relative or a custom +FORMAT (strftime syntax)
+    #[clap(long)]
+    pub time_style: Option<TimeStyle>,
+
+    /// Compute a digest of the contents of each file, and of each directory from its entries (blake3 or sha256)
+    #[clap(long, value_name = "ALGORITHM")]
+    pub hash: Option<HashAlgorithm>,
+
+    /// Abort on the first entry that cannot be read, instead of reporting it in the tree
+    #[clap(long)]
+    pub strict: bool,
+
     /// Disables loading the configuration file
     #[clap(long, alias = "nocfg")]
     pub no_config: bool,
@@ -83,3 +360,78 @@ pub struct Args {
 pub fn parse() -> Args {
     Args::parse()
 }
+
+/// Converts the options of a tree diagram into a ConfigBuilder
+impl From<DiagramOptions> for ConfigBuilder {
+    fn from(args: DiagramOptions) -> Self {
+        let mut builder = ConfigBuilder::default();
+        builder.prefix = args.prefix;
+        builder.last_prefix = args.last_prefix;
+        builder.child_prefix = args.child_prefix;
+        builder.charset = args.charset;
+        builder
+    }
+}
+
+/// Converts CLI options into a ConfigBuilder, without a root (see `Args::root`)
+impl From<Options> for ConfigBuilder {
+    fn from(args: Options) -> Self {
+        // The builder is `#[non_exhaustive]`, so it is filled in field by field
+        let mut builder = ConfigBuilder::default();
+        builder.full_path = args.full_path;
+        builder.prefix = args.prefix;
+        builder.last_prefix = args.last_prefix;
+        builder.child_prefix = args.child_prefix;
+        builder.charset = args.charset;
+        builder.show_all = args.show_all;
+        builder.hidden = args.hidden;
+        builder.no_ignore = args.no_ignore;
+        builder.no_ignore_vcs = args.no_ignore_vcs;
+        builder.no_ignore_parent = args.no_ignore_parent;
+        builder.no_ignore_global = args.no_ignore_global;
+        builder.include = args.include;
+        builder.exclude = args.exclude;
+        builder.pattern_file = args.pattern_file;
+        builder.exclude_dirs = args.exclude_dirs;
+        builder.regex_include = args.regex_include;
+        builder.regex_exclude = args.regex_exclude;
+        builder.regex_path = args.regex_path;
+        builder.regex_ignore_case = args.regex_ignore_case;
+        builder.types = args.types;
+        builder.min_size = args.min_size;
+        builder.max_size = args.max_size;
+        builder.newer = args.newer;
+        builder.older = args.older;
+        builder.perm = args.perm;
+        builder.user = args.user;
+        builder.group = args.group;
+        builder.expression = args.expression;
+        builder.contains = args.contains;
+        builder.binary = args.binary;
+        builder.count_matches = args.count_matches;
+        builder.prune = args.prune;
+        builder.ignore = args.ignore;
+        builder.directory = args.directory;
+        builder.summary = args.summary;
+        builder.size = args.size;
+        builder.size_format = args.size_format;
+        builder.max_depth = args.max_depth;
+        builder.format = args.format;
+        builder.no_color = args.no_color;
+        builder.threads = args.threads;
+        builder.strict = args.strict;
+        builder.metadata = args.metadata;
+        builder.columns = args.columns;
+        builder.time_style = args.time_style;
+        builder.sort = args.sort;
+        builder.group_dirs = args.group_dirs;
+        builder.reverse = args.reverse;
+        builder.disk_usage = args.disk_usage;
+        builder.git_status = args.git_status;
+        builder.changed = args.changed;
+        builder.merge_base = args.merge_base;
+        builder.rev = args.rev;
+        builder.hash = args.hash;
+        builder
+    }
+}
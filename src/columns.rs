@@ -0,0 +1,224 @@
+//! This module describes the columns of file details that can be shown before the
+//! tree, in the style of `ls -l`.
+
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::helpers;
+use crate::tree::{NodeType, TreeNode};
+
+/// The width of the subject column, which longer subjects are truncated to, so that the
+/// tree does not shift from one line to the next
+const SUBJECT_WIDTH: usize = 32;
+
+/// How many hexadecimal digits of a digest are shown, which is plenty to tell entries apart
+const DIGEST_WIDTH: usize = 16;
+
+/// A column of file details
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Column {
+    /// The type and permissions, e.g. `drwxr-xr-x`
+    Perms,
+    /// The name (or ID) of the owning user
+    User,
+    /// The name (or ID) of the owning group
+    Group,
+    /// The size, in the configured size format
+    Size,
+    /// The time of the last modification
+    Mtime,
+    /// The number of hard links
+    Links,
+    /// The inode number
+    Inode,
+    /// The git status, e.g. `M ` or `??` (see `Config::git_status`)
+    Git,
+    /// The change relative to a git revision, e.g. `A` or `M` (see `Config::changed`)
+    Change,
+    /// The date of the last commit that touched the entry
+    CommitDate,
+    /// The abbreviated hash of the last commit that touched the entry
+    Commit,
+    /// The author of the last commit that touched the entry
+    Author,
+    /// The subject of the last commit that touched the entry
+    Subject,
+    /// How the entry differs between two trees, e.g. `+` or `~` (see `diff::diff`)
+    Diff,
+    /// The side of a comparison that the entry is on, e.g. `<` for only on the left, or
+    /// `~` if it differs (see `diff::compare`)
+    Side,
+    /// The abbreviated digest of the contents of the entry (see `Config::hash`)
+    Digest,
+}
+
+impl Column {
+    /// Whether the column needs the metadata collected with `Config::metadata`
+    pub fn needs_metadata(&self) -> bool {
+        !matches!(
+            self,
+            Self::Size | Self::Git | Self::Change | Self::Diff | Self::Side | Self::Digest
+        ) && !self.needs_history()
+    }
+
+    /// Whether the column needs the last commit of each entry (see `Config::collect_history`)
+    pub fn needs_history(&self) -> bool {
+        matches!(
+            self,
+            Self::CommitDate | Self::Commit | Self::Author | Self::Subject
+        )
+    }
+
+    /// Whether the values of the column are aligned to the right
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Self::Size | Self::Links | Self::Inode)
+    }
+
+    /// The minimum width of the column, so that most values line up from the first line on
+    pub fn min_width(&self, cfg: &Config) -> usize {
+        match self {
+            Self::Perms => 10,
+            Self::User | Self::Group => 8,
+            Self::Size => 8,
+            Self::Mtime | Self::CommitDate => match cfg.time_style {
+                helpers::time::TimeStyle::Iso => 16,
+                helpers::time::TimeStyle::Relative => 14,
+                helpers::time::TimeStyle::Custom(_) => 0,
+            },
+            Self::Links => 2,
+            Self::Inode => 8,
+            Self::Git => 2,
+            Self::Change => 1,
+            Self::Commit => 7,
+            Self::Author => 8,
+            Self::Subject => SUBJECT_WIDTH,
+            Self::Diff | Self::Side => 1,
+            Self::Digest => DIGEST_WIDTH,
+        }
+    }
+
+    /// Renders the value of the column for a node, or `-` if it is not known
+    pub fn render(&self, node: &TreeNode, cfg: &Config, now: SystemTime) -> String {
+        let metadata = node.metadata.as_ref();
+        let value = match self {
+            Self::Perms => metadata
+                .and_then(|m| m.mode)
+                .map(|mode| permissions(&node.node_type, mode)),
+            Self::User => metadata.and_then(|m| m.user.clone().or(m.uid.map(|id| id.to_string()))),
+            Self::Group => {
+                metadata.and_then(|m| m.group.clone().or(m.gid.map(|id| id.to_string())))
+            }
+            Self::Size => node
+                .size
+                .map(|size| helpers::bytes::format(size, &cfg.size_format)),
+            Self::Mtime => metadata
+                .and_then(|m| m.modified)
+                .map(|time| helpers::time::format(time, &cfg.time_style, now)),
+            Self::Links => metadata.and_then(|m| m.links).map(|n| n.to_string()),
+            Self::Inode => metadata.and_then(|m| m.inode).map(|n| n.to_string()),
+            // A clean entry has a blank marker, like in `git status --short`
+            Self::Git => Some(node.git.map(|status| status.marker()).unwrap_or_default()),
+            Self::Change => Some(node.change.map_or(' ', |change| change.code()).to_string()),
+            Self::CommitDate => node
+                .commit
+                .as_ref()
+                .map(|commit| helpers::time::format(commit.date, &cfg.time_style, now)),
+            Self::Commit => node.commit.as_ref().map(|c| c.short_hash.clone()),
+            Self::Author => node.commit.as_ref().map(|c| c.author.clone()),
+            Self::Subject => node
+                .commit
+                .as_ref()
+                .map(|c| truncate(&c.subject, SUBJECT_WIDTH)),
+            Self::Diff => Some(
+                node.diff
+                    .as_ref()
+                    .map_or(' ', |diff| diff.status.marker())
+                    .to_string(),
+            ),
+            Self::Side => Some(
+                node.diff
+                    .as_ref()
+                    .map_or(' ', |diff| diff.status.side_marker())
+                    .to_string(),
+            ),
+            Self::Digest => node
+                .hash
+                .as_ref()
+                .map(|digest| digest.chars().take(DIGEST_WIDTH).collect()),
+        };
+        value.unwrap_or_else(|| "-".to_string())
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "perms" | "permissions" | "mode" => Ok(Self::Perms),
+            "user" | "owner" => Ok(Self::User),
+            "group" => Ok(Self::Group),
+            "size" => Ok(Self::Size),
+            "mtime" | "modified" | "time" | "date" => Ok(Self::Mtime),
+            "links" | "nlink" => Ok(Self::Links),
+            "inode" => Ok(Self::Inode),
+            "git" | "status" => Ok(Self::Git),
+            "change" => Ok(Self::Change),
+            "commit-date" | "last-commit" => Ok(Self::CommitDate),
+            "commit" | "hash" => Ok(Self::Commit),
+            "author" => Ok(Self::Author),
+            "subject" => Ok(Self::Subject),
+            "diff" => Ok(Self::Diff),
+            "side" => Ok(Self::Side),
+            "digest" | "checksum" => Ok(Self::Digest),
+            e => Err(format!("Unknown column: {e}")),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Column {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<Column>().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Truncates a string to a number of characters, ending it with an ellipsis if it is cut
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_string();
+    }
+    let mut truncated: String = s.chars().take(width - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Renders the type and permission bits of an entry like `ls -l`, e.g. `drwxr-xr-x`
+pub fn permissions(node_type: &NodeType, mode: u32) -> String {
+    let mut perms = String::with_capacity(10);
+    perms.push(match node_type {
+        NodeType::Directory => 'd',
+        NodeType::SymbolicLink => 'l',
+        NodeType::Unknown => '?',
+        _ => '-',
+    });
+
+    // The read, write and execute bits of the user, group and others, along with the
+    // special bit that replaces the execute bit of each (setuid, setgid and sticky)
+    let classes = [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')];
+    for (shift, special, special_char) in classes {
+        let bits = (mode >> shift) & 0o7;
+        perms.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        perms.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        perms.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    perms
+}
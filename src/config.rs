@@ -8,14 +8,22 @@ use std::fs;
 use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 
-use crate::cli;
-use crate::formatter::OutputFormat;
+use crate::columns::Column;
+use crate::formatter::{Charset, OutputFormat};
+use crate::hash::HashAlgorithm;
 use crate::helpers::{
     self,
     ansi::{Ansi, AnsiString},
+    time::PointInTime,
 };
+use crate::predicate::{FileKind, PermMatch};
+use crate::sort::{GroupDirs, SortKey};
 
 /// Represents the final, merged configuration from all sources
+///
+/// A `Config` is usually obtained from a `ConfigBuilder`, but `Config::default()` can
+/// also be used as a starting point and adjusted field by field.
+#[non_exhaustive]
 pub struct Config {
     /// The root directory to start the tree from
     pub root: PathBuf,
@@ -27,12 +35,61 @@ pub struct Config {
     pub last_prefix: String,
     /// The prefix string to use for child entries
     pub child_prefix: String,
-    /// Whether to show all files and directories, including hidden files
-    pub show_all: bool,
-    /// A pattern to include files that match the glob syntax
-    pub include: Option<String>,
-    /// A pattern to exclude files that match the glob syntax
-    pub exclude: Option<String>,
+    /// The characters the default prefixes are drawn with
+    pub charset: Charset,
+    /// Whether to show hidden files and directories (those whose name starts with a dot)
+    pub hidden: bool,
+    /// Whether to show the entries matched by ignore files (gitignore and custom ignore files)
+    pub no_ignore: bool,
+    /// Whether to show the entries matched by git's ignore files (`.gitignore`, `.git/info/exclude`
+    /// and the global excludes file), while still honoring the custom ignore files
+    pub no_ignore_vcs: bool,
+    /// Whether to skip the `.gitignore` files of the directories above the root
+    pub no_ignore_parent: bool,
+    /// Whether to skip git's global excludes file (`core.excludesFile`)
+    pub no_ignore_global: bool,
+    /// Patterns (glob syntax) of the files to show, the last matching pattern wins
+    pub include: Vec<String>,
+    /// Patterns (glob syntax) of the files to hide, the last matching pattern wins
+    pub exclude: Vec<String>,
+    /// A file with more include patterns, one per line
+    pub pattern_file: Option<PathBuf>,
+    /// Whether the exclude patterns also hide directories, along with their contents
+    pub exclude_dirs: bool,
+    /// Regular expressions of the files to show (any of them may match)
+    pub regex_include: Vec<String>,
+    /// Regular expressions of the files to hide (any of them may match)
+    pub regex_exclude: Vec<String>,
+    /// Whether the regular expressions match the path relative to the root, instead of the name
+    pub regex_path: bool,
+    /// Whether the regular expressions are case-insensitive
+    pub regex_ignore_case: bool,
+    /// The kinds of entries to show (any of them), e.g. only files or symbolic links
+    pub types: Vec<FileKind>,
+    /// The minimum size of the files to show, in bytes
+    pub min_size: Option<u64>,
+    /// The maximum size of the files to show, in bytes
+    pub max_size: Option<u64>,
+    /// Show only the files modified after this point in time
+    pub newer: Option<PointInTime>,
+    /// Show only the files modified before this point in time
+    pub older: Option<PointInTime>,
+    /// Show only the files whose permission bits pass this test
+    pub perm: Option<PermMatch>,
+    /// Show only the files owned by this user (name or ID)
+    pub user: Option<String>,
+    /// Show only the files owned by this group (name or ID)
+    pub group: Option<String>,
+    /// A filter expression that the files have to match (see the `expr` module)
+    pub expression: Option<String>,
+    /// A regular expression that the contents of the files have to match
+    pub contains: Option<String>,
+    /// Whether the content search also searches binary files
+    pub binary: bool,
+    /// Whether to count the matches of the content search in each file
+    pub count_matches: bool,
+    /// Whether to hide the directories that contain no files, at any depth
+    pub prune: bool,
     /// Custom ignore files
     pub ignore: Vec<String>,
     /// Whether to show only directories
@@ -49,6 +106,85 @@ pub struct Config {
     pub format: OutputFormat,
     /// Whether to disable ANSI colors in the output
     pub no_color: bool,
+    /// The number of threads to use for the traversal (defaults to the number of CPUs)
+    pub threads: Option<usize>,
+    /// Whether to abort on the first unreadable entry, instead of reporting it in the tree
+    pub strict: bool,
+    /// Whether to collect the file metadata (permissions, owner, timestamps, inode, etc.)
+    pub metadata: bool,
+    /// The columns of file details to show before the tree, in order
+    pub columns: Vec<Column>,
+    /// How to show timestamps in the text output
+    pub time_style: helpers::time::TimeStyle,
+    /// The key to sort the entries of each directory by
+    pub sort: SortKey,
+    /// Where to place directories among the other entries
+    pub group_dirs: GroupDirs,
+    /// Whether to reverse the sort order
+    pub reverse: bool,
+    /// Whether sizes are the space used on disk (`st_blocks * 512`), instead of the apparent size
+    pub disk_usage: bool,
+    /// Whether to show the git status of the entries, as a column and as coloured names
+    pub git_status: bool,
+    /// The git revision to compare with, showing only the files that differ from it
+    pub changed: Option<String>,
+    /// Whether to compare with the merge base of `changed` and `HEAD`, instead
+    pub merge_base: bool,
+    /// The git revision (any tree-ish) whose tree to show, instead of the files on disk
+    pub rev: Option<String>,
+    /// The algorithm to compute a digest of the contents of each file with, if any
+    ///
+    /// Directories then get a digest of the names, types and digests of their entries.
+    pub hash: Option<HashAlgorithm>,
+}
+
+impl Config {
+    /// Whether the file metadata needs to be collected, either because it was requested
+    /// or because something else depends on it
+    pub fn collect_metadata(&self) -> bool {
+        self.metadata
+            || self.columns.iter().any(Column::needs_metadata)
+            || self.sort.needs_metadata()
+    }
+
+    /// Whether the git status of the entries needs to be collected, either because it was
+    /// requested or because it is shown as a column
+    pub fn collect_git_status(&self) -> bool {
+        self.git_status || self.columns.contains(&Column::Git)
+    }
+
+    /// Whether the last commit that touched each entry needs to be collected, either for
+    /// a column or for sorting
+    pub fn collect_history(&self) -> bool {
+        self.columns.iter().any(Column::needs_history) || self.sort == SortKey::Commit
+    }
+
+    /// The algorithm to compute the digests of the entries with, if they are needed,
+    /// either because it was requested or because they are shown as a column
+    pub fn hash_algorithm(&self) -> Option<HashAlgorithm> {
+        self.hash.or_else(|| {
+            self.columns
+                .contains(&Column::Digest)
+                .then(HashAlgorithm::default)
+        })
+    }
+
+    /// Whether directories should carry the total size of their subtree
+    pub fn measure_dirs(&self) -> bool {
+        self.size || self.columns.contains(&Column::Size) || self.sort == SortKey::Size
+    }
+
+    /// Whether the output depends on information that is only known once a directory
+    /// has been walked entirely (e.g. its size or digest), so the whole tree has to be built
+    /// before it can be formatted
+    ///
+    /// This is also the case for the columns of the text output, whose widths depend on
+    /// the values of all the entries.
+    pub fn requires_full_tree(&self) -> bool {
+        self.measure_dirs()
+            || self.hash_algorithm().is_some()
+            || (matches!(self.format, OutputFormat::Text) && !self.columns.is_empty())
+    }
 }
 
 impl Default for Config {
@@ -57,12 +193,36 @@ impl Default for Config {
         Self {
             root: PathBuf::from("."),
             full_path: false,
-            prefix: "├── ".to_string(),
-            last_prefix: "└── ".to_string(),
-            child_prefix: "│   ".to_string(),
-            show_all: false,
-            include: None,
-            exclude: None,
+            prefix: Charset::Utf8.prefix().to_string(),
+            last_prefix: Charset::Utf8.last_prefix().to_string(),
+            child_prefix: Charset::Utf8.child_prefix().to_string(),
+            charset: Charset::Utf8,
+            hidden: false,
+            no_ignore: false,
+            no_ignore_vcs: false,
+            no_ignore_parent: false,
+            no_ignore_global: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            pattern_file: None,
+            exclude_dirs: false,
+            regex_include: Vec::new(),
+            regex_exclude: Vec::new(),
+            regex_path: false,
+            regex_ignore_case: false,
+            types: Vec::new(),
+            min_size: None,
+            max_size: None,
+            newer: None,
+            older: None,
+            perm: None,
+            user: None,
+            group: None,
+            expression: None,
+            contains: None,
+            binary: false,
+            count_matches: false,
+            prune: false,
             ignore: Vec::new(),
             directory: false,
             summary: false,
@@ -71,6 +231,20 @@ impl Default for Config {
             max_depth: None,
             format: OutputFormat::Text,
             no_color: std::env::var("NO_COLOR").is_ok(),
+            threads: None,
+            strict: false,
+            metadata: false,
+            columns: Vec::new(),
+            time_style: helpers::time::TimeStyle::Iso,
+            sort: SortKey::Name,
+            group_dirs: GroupDirs::Mixed,
+            reverse: false,
+            disk_usage: false,
+            git_status: false,
+            changed: None,
+            merge_base: false,
+            rev: None,
+            hash: None,
         }
     }
 }
@@ -79,17 +253,45 @@ impl Default for Config {
 ///
 /// This builder allows for layered configuration, where settings from different
 /// sources can be merged. Command-line arguments take precedence over file-based
-/// settings.
+/// settings. Each field mirrors the field of the same name in `Config`, with `None`
+/// (or `false`) meaning that the default should be used.
 #[derive(Default, Debug)]
+#[non_exhaustive]
 pub struct ConfigBuilder {
     pub root: Option<PathBuf>,
     pub full_path: bool,
     pub prefix: Option<String>,
     pub last_prefix: Option<String>,
     pub child_prefix: Option<String>,
+    pub charset: Option<Charset>,
+    /// Shortcut for both `hidden` and `no_ignore`
     pub show_all: bool,
-    pub include: Option<String>,
-    pub exclude: Option<String>,
+    pub hidden: bool,
+    pub no_ignore: bool,
+    pub no_ignore_vcs: bool,
+    pub no_ignore_parent: bool,
+    pub no_ignore_global: bool,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub pattern_file: Option<PathBuf>,
+    pub exclude_dirs: bool,
+    pub regex_include: Option<Vec<String>>,
+    pub regex_exclude: Option<Vec<String>>,
+    pub regex_path: bool,
+    pub regex_ignore_case: bool,
+    pub types: Option<Vec<FileKind>>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub newer: Option<PointInTime>,
+    pub older: Option<PointInTime>,
+    pub perm: Option<PermMatch>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub expression: Option<String>,
+    pub contains: Option<String>,
+    pub binary: bool,
+    pub count_matches: bool,
+    pub prune: bool,
     pub ignore: Option<Vec<String>>,
     pub directory: bool,
     pub summary: bool,
@@ -98,6 +300,20 @@ pub struct ConfigBuilder {
     pub max_depth: Option<usize>,
     pub format: Option<OutputFormat>,
     pub no_color: bool,
+    pub threads: Option<usize>,
+    pub strict: bool,
+    pub metadata: bool,
+    pub columns: Option<Vec<Column>>,
+    pub time_style: Option<helpers::time::TimeStyle>,
+    pub sort: Option<SortKey>,
+    pub group_dirs: Option<GroupDirs>,
+    pub reverse: bool,
+    pub disk_usage: bool,
+    pub git_status: bool,
+    pub changed: Option<String>,
+    pub merge_base: bool,
+    pub rev: Option<String>,
+    pub hash: Option<HashAlgorithm>,
 }
 
 impl ConfigBuilder {
@@ -109,9 +325,34 @@ impl ConfigBuilder {
         self.prefix = self.prefix.or(other.prefix);
         self.last_prefix = self.last_prefix.or(other.last_prefix);
         self.child_prefix = self.child_prefix.or(other.child_prefix);
+        self.charset = self.charset.or(other.charset);
         self.show_all = self.show_all || other.show_all;
+        self.hidden = self.hidden || other.hidden;
+        self.no_ignore = self.no_ignore || other.no_ignore;
+        self.no_ignore_vcs = self.no_ignore_vcs || other.no_ignore_vcs;
+        self.no_ignore_parent = self.no_ignore_parent || other.no_ignore_parent;
+        self.no_ignore_global = self.no_ignore_global || other.no_ignore_global;
         self.include = self.include.or(other.include);
         self.exclude = self.exclude.or(other.exclude);
+        self.pattern_file = self.pattern_file.or(other.pattern_file);
+        self.exclude_dirs = self.exclude_dirs || other.exclude_dirs;
+        self.regex_include = self.regex_include.or(other.regex_include);
+        self.regex_exclude = self.regex_exclude.or(other.regex_exclude);
+        self.regex_path = self.regex_path || other.regex_path;
+        self.regex_ignore_case = self.regex_ignore_case || other.regex_ignore_case;
+        self.types = self.types.or(other.types);
+        self.min_size = self.min_size.or(other.min_size);
+        self.max_size = self.max_size.or(other.max_size);
+        self.newer = self.newer.or(other.newer);
+        self.older = self.older.or(other.older);
+        self.perm = self.perm.or(other.perm);
+        self.user = self.user.or(other.user);
+        self.group = self.group.or(other.group);
+        self.expression = self.expression.or(other.expression);
+        self.contains = self.contains.or(other.contains);
+        self.binary = self.binary || other.binary;
+        self.count_matches = self.count_matches || other.count_matches;
+        self.prune = self.prune || other.prune;
         self.ignore = self.ignore.or(other.ignore);
         self.directory = self.directory || other.directory;
         self.summary = self.summary || other.summary;
@@ -120,21 +361,64 @@ impl ConfigBuilder {
         self.max_depth = self.max_depth.or(other.max_depth);
         self.format = self.format.or(other.format);
         self.no_color = self.no_color || other.no_color;
+        self.threads = self.threads.or(other.threads);
+        self.strict = self.strict || other.strict;
+        self.metadata = self.metadata || other.metadata;
+        self.columns = self.columns.or(other.columns);
+        self.time_style = self.time_style.or(other.time_style);
+        self.sort = self.sort.or(other.sort);
+        self.group_dirs = self.group_dirs.or(other.group_dirs);
+        self.reverse = self.reverse || other.reverse;
+        self.disk_usage = self.disk_usage || other.disk_usage;
+        self.git_status = self.git_status || other.git_status;
+        self.changed = self.changed.or(other.changed);
+        self.merge_base = self.merge_base || other.merge_base;
+        self.rev = self.rev.or(other.rev);
+        self.hash = self.hash.or(other.hash);
         self
     }
 
     /// Builds the final Config struct from the ConfigBuilder, applying default values.
     pub fn build(self) -> Config {
         let defaults = Config::default();
+        let charset = self.charset.unwrap_or(defaults.charset);
         Config {
             root: self.root.unwrap_or(defaults.root),
             full_path: self.full_path,
-            prefix: self.prefix.unwrap_or(defaults.prefix),
-            last_prefix: self.last_prefix.unwrap_or(defaults.last_prefix),
-            child_prefix: self.child_prefix.unwrap_or(defaults.child_prefix),
-            show_all: self.show_all,
-            include: self.include,
-            exclude: self.exclude,
+            prefix: self.prefix.unwrap_or_else(|| charset.prefix().to_string()),
+            last_prefix: self
+                .last_prefix
+                .unwrap_or_else(|| charset.last_prefix().to_string()),
+            child_prefix: self
+                .child_prefix
+                .unwrap_or_else(|| charset.child_prefix().to_string()),
+            charset,
+            hidden: self.hidden || self.show_all,
+            no_ignore: self.no_ignore || self.show_all,
+            no_ignore_vcs: self.no_ignore_vcs,
+            no_ignore_parent: self.no_ignore_parent,
+            no_ignore_global: self.no_ignore_global,
+            include: self.include.unwrap_or(defaults.include),
+            exclude: self.exclude.unwrap_or(defaults.exclude),
+            pattern_file: self.pattern_file,
+            exclude_dirs: self.exclude_dirs,
+            regex_include: self.regex_include.unwrap_or(defaults.regex_include),
+            regex_exclude: self.regex_exclude.unwrap_or(defaults.regex_exclude),
+            regex_path: self.regex_path,
+            regex_ignore_case: self.regex_ignore_case,
+            types: self.types.unwrap_or(defaults.types),
+            min_size: self.min_size,
+            max_size: self.max_size,
+            newer: self.newer,
+            older: self.older,
+            perm: self.perm,
+            user: self.user,
+            group: self.group,
+            expression: self.expression,
+            contains: self.contains,
+            binary: self.binary,
+            count_matches: self.count_matches,
+            prune: self.prune,
             ignore: self.ignore.unwrap_or(defaults.ignore),
             directory: self.directory,
             summary: self.summary,
@@ -143,30 +427,20 @@ impl ConfigBuilder {
             max_depth: self.max_depth,
             format: self.format.unwrap_or(defaults.format),
             no_color: self.no_color || !std::io::stdout().is_terminal(),
-        }
-    }
-}
-
-/// Converts CLI arguments into a ConfigBuilder
-impl From<cli::Args> for ConfigBuilder {
-    fn from(args: cli::Args) -> Self {
-        Self {
-            root: args.root,
-            full_path: args.full_path,
-            prefix: args.prefix,
-            last_prefix: args.last_prefix,
-            child_prefix: args.child_prefix,
-            show_all: args.show_all,
-            include: args.include,
-            exclude: args.exclude,
-            ignore: args.ignore,
-            directory: args.directory,
-            summary: args.summary,
-            size: args.size,
-            size_format: args.size_format,
-            max_depth: args.max_depth,
-            format: args.format,
-            no_color: args.no_color,
+            threads: self.threads,
+            strict: self.strict,
+            metadata: self.metadata,
+            columns: self.columns.unwrap_or(defaults.columns),
+            time_style: self.time_style.unwrap_or(defaults.time_style),
+            sort: self.sort.unwrap_or(defaults.sort),
+            group_dirs: self.group_dirs.unwrap_or(defaults.group_dirs),
+            reverse: self.reverse,
+            disk_usage: self.disk_usage,
+            git_status: self.git_status,
+            changed: self.changed,
+            merge_base: self.merge_base,
+            rev: self.rev,
+            hash: self.hash,
         }
     }
 }
@@ -182,9 +456,42 @@ pub struct FileConfig {
     pub prefix: Option<String>,
     pub last_prefix: Option<String>,
     pub child_prefix: Option<String>,
+    pub charset: Option<Charset>,
     pub show_all: Option<bool>,
-    pub include: Option<String>,
-    pub exclude: Option<String>,
+    pub hidden: Option<bool>,
+    pub no_ignore: Option<bool>,
+    pub no_ignore_vcs: Option<bool>,
+    pub no_ignore_parent: Option<bool>,
+    pub no_ignore_global: Option<bool>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub include: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub exclude: Option<Vec<String>>,
+    pub pattern_file: Option<PathBuf>,
+    pub exclude_dirs: Option<bool>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub regex_include: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub regex_exclude: Option<Vec<String>>,
+    pub regex_path: Option<bool>,
+    pub regex_ignore_case: Option<bool>,
+    #[serde(rename = "type")]
+    pub types: Option<Vec<FileKind>>,
+    #[serde(default, deserialize_with = "optional_size")]
+    pub min_size: Option<u64>,
+    #[serde(default, deserialize_with = "optional_size")]
+    pub max_size: Option<u64>,
+    pub newer: Option<PointInTime>,
+    pub older: Option<PointInTime>,
+    pub perm: Option<PermMatch>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    #[serde(rename = "where")]
+    pub expression: Option<String>,
+    pub contains: Option<String>,
+    pub binary: Option<bool>,
+    pub count_matches: Option<bool>,
+    pub prune: Option<bool>,
     pub ignore: Option<Vec<String>>,
     pub directory: Option<bool>,
     pub summary: Option<bool>,
@@ -193,6 +500,61 @@ pub struct FileConfig {
     pub max_depth: Option<usize>,
     pub format: Option<OutputFormat>,
     pub no_color: Option<bool>,
+    pub threads: Option<usize>,
+    pub strict: Option<bool>,
+    pub metadata: Option<bool>,
+    pub columns: Option<Vec<Column>>,
+    pub time_style: Option<helpers::time::TimeStyle>,
+    pub sort: Option<SortKey>,
+    pub group_dirs: Option<GroupDirs>,
+    pub reverse: Option<bool>,
+    pub disk_usage: Option<bool>,
+    pub git_status: Option<bool>,
+    pub changed: Option<String>,
+    pub merge_base: Option<bool>,
+    pub rev: Option<String>,
+    pub hash: Option<HashAlgorithm>,
+}
+
+/// Deserializes either a single string or a list of strings
+fn one_or_many<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(
+        Option::<OneOrMany>::deserialize(deserializer)?.map(|value| match value {
+            OneOrMany::One(pattern) => vec![pattern],
+            OneOrMany::Many(patterns) => patterns,
+        }),
+    )
+}
+
+/// Deserializes a size, either as a number of bytes or as a string with a unit (e.g. `10k`)
+fn optional_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => helpers::bytes::parse(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
 }
 
 /// Converts a `FileConfig` into a `ConfigBuilder`.
@@ -204,9 +566,34 @@ impl From<FileConfig> for ConfigBuilder {
             prefix: file_config.prefix,
             last_prefix: file_config.last_prefix,
             child_prefix: file_config.child_prefix,
+            charset: file_config.charset,
             show_all: file_config.show_all.unwrap_or_default(),
+            hidden: file_config.hidden.unwrap_or_default(),
+            no_ignore: file_config.no_ignore.unwrap_or_default(),
+            no_ignore_vcs: file_config.no_ignore_vcs.unwrap_or_default(),
+            no_ignore_parent: file_config.no_ignore_parent.unwrap_or_default(),
+            no_ignore_global: file_config.no_ignore_global.unwrap_or_default(),
             include: file_config.include,
             exclude: file_config.exclude,
+            pattern_file: file_config.pattern_file,
+            exclude_dirs: file_config.exclude_dirs.unwrap_or_default(),
+            regex_include: file_config.regex_include,
+            regex_exclude: file_config.regex_exclude,
+            regex_path: file_config.regex_path.unwrap_or_default(),
+            regex_ignore_case: file_config.regex_ignore_case.unwrap_or_default(),
+            types: file_config.types,
+            min_size: file_config.min_size,
+            max_size: file_config.max_size,
+            newer: file_config.newer,
+            older: file_config.older,
+            perm: file_config.perm,
+            user: file_config.user,
+            group: file_config.group,
+            expression: file_config.expression,
+            contains: file_config.contains,
+            binary: file_config.binary.unwrap_or_default(),
+            count_matches: file_config.count_matches.unwrap_or_default(),
+            prune: file_config.prune.unwrap_or_default(),
             ignore: file_config.ignore,
             directory: file_config.directory.unwrap_or_default(),
             summary: file_config.summary.unwrap_or_default(),
@@ -215,6 +602,20 @@ impl From<FileConfig> for ConfigBuilder {
             max_depth: file_config.max_depth,
             format: file_config.format,
             no_color: file_config.no_color.unwrap_or_default(),
+            threads: file_config.threads,
+            strict: file_config.strict.unwrap_or_default(),
+            metadata: file_config.metadata.unwrap_or_default(),
+            columns: file_config.columns,
+            time_style: file_config.time_style,
+            sort: file_config.sort,
+            group_dirs: file_config.group_dirs,
+            reverse: file_config.reverse.unwrap_or_default(),
+            disk_usage: file_config.disk_usage.unwrap_or_default(),
+            git_status: file_config.git_status.unwrap_or_default(),
+            changed: file_config.changed,
+            merge_base: file_config.merge_base.unwrap_or_default(),
+            rev: file_config.rev,
+            hash: file_config.hash,
         }
     }
 }
@@ -242,23 +643,23 @@ fn get_config_path() -> Option<PathBuf> {
 /// Reads and parses the JSON configuration file. If the file doesn't exist,
 /// is inaccessible, or contains invalid JSON, it returns a default, empty configuration
 pub fn load_file() -> FileConfig {
-    if let Some(path) = get_config_path() {
-        if let Ok(content) = fs::read_to_string(&path) {
-            // Ignore empty or whitespace-only config files
-            if content.trim().is_empty() {
-                return FileConfig::default();
-            }
-            // Attempt to parse the config, printing an error if it fails
-            match serde_json::from_str(&content) {
-                Ok(config) => return config,
-                Err(e) => {
-                    eprintln!(
-                        "{} Failed to parse config file at {}: {}",
-                        " Warning ".ansi(&[Ansi::BgYellow]),
-                        path.display(),
-                        e
-                    );
-                }
+    if let Some(path) = get_config_path()
+        && let Ok(content) = fs::read_to_string(&path)
+    {
+        // Ignore empty or whitespace-only config files
+        if content.trim().is_empty() {
+            return FileConfig::default();
+        }
+        // Attempt to parse the config, printing an error if it fails
+        match serde_json::from_str(&content) {
+            Ok(config) => return config,
+            Err(e) => {
+                eprintln!(
+                    "{} Failed to parse config file at {}: {}",
+                    " Warning ".ansi(&[Ansi::BgYellow]),
+                    path.display(),
+                    e
+                );
             }
         }
     }
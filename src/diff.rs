@@ -0,0 +1,523 @@
+//! This module compares two trees, e.g. a snapshot and the directory it was taken of,
+//! merging them into a single tree whose nodes are marked with how they differ.
+//!
+//! The entries are matched by their path relative to the root of each tree. Files are
+//! compared by type and symbolic link target, and then either by digest when both sides
+//! have one (see `Config::hash`) and by size and modification time otherwise, or by
+//! the details chosen with `CompareBy`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::sort;
+use crate::stats::Statistics;
+use crate::tree::{NodeType, TreeNode};
+
+/// How an entry differs between the old and the new tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum DiffStatus {
+    /// Only in the new tree
+    Added,
+    /// Only in the old tree
+    Removed,
+    /// In both trees, with different contents (or, for a directory, different entries)
+    Modified,
+    /// A file that was moved (or renamed) from another path of the old tree
+    Moved,
+    /// In both trees, the same
+    Unchanged,
+}
+
+impl DiffStatus {
+    /// Returns the marker shown in the diff column, e.g. `+` for an added entry
+    pub fn marker(&self) -> char {
+        match self {
+            Self::Added => '+',
+            Self::Removed => '-',
+            Self::Modified => '~',
+            Self::Moved => '>',
+            Self::Unchanged => ' ',
+        }
+    }
+
+    /// Returns the marker shown in the side column of a comparison (see `compare`), e.g.
+    /// `<` for an entry only on the left
+    pub fn side_marker(&self) -> char {
+        match self {
+            Self::Removed => '<',
+            Self::Added | Self::Moved => '>',
+            Self::Modified => '~',
+            Self::Unchanged => ' ',
+        }
+    }
+}
+
+/// How an entry of a merged tree differs between the old and the new tree
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diff {
+    /// How the entry differs
+    pub status: DiffStatus,
+    /// The change of the size in bytes, summed over the files below a directory
+    ///
+    /// A moved file counts towards neither the directory it left nor the one it joined.
+    pub size_delta: i64,
+    /// The path, relative to the root of the old tree, that a moved file was moved from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved_from: Option<PathBuf>,
+}
+
+/// A detail that two versions of a file are compared by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompareBy {
+    /// The size
+    Size,
+    /// The modification time, to the second
+    Mtime,
+    /// The digest of the contents, or the size if either version has none
+    Hash,
+}
+
+impl std::str::FromStr for CompareBy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "size" => Ok(Self::Size),
+            "mtime" | "time" | "modified" => Ok(Self::Mtime),
+            "hash" | "content" => Ok(Self::Hash),
+            e => Err(format!(
+                "Unknown comparison: {e} (expected size, mtime or hash)"
+            )),
+        }
+    }
+}
+
+/// How two versions of a file are compared
+enum Criteria<'a> {
+    /// By digest when both versions have one, and by size and modification time otherwise
+    Auto,
+    /// By all of these details
+    By(&'a [CompareBy]),
+}
+
+impl Diff {
+    /// Creates the diff of an entry, without a size delta
+    fn new(status: DiffStatus) -> Self {
+        Self {
+            status,
+            size_delta: 0,
+            moved_from: None,
+        }
+    }
+}
+
+/// Merges an old and a new tree into one, marking every node with its `Diff`
+///
+/// The merged tree has the paths of the new tree, and its entries are sorted as
+/// configured. Files that only appear in the old tree and files that only appear in
+/// the new one are paired up as moves when they have the same digest (or, unless both
+/// trees have digests, the same name and size).
+pub fn diff(old: TreeNode, new: TreeNode, cfg: &Config) -> TreeNode {
+    let by_hash = has_hashes(&old) && has_hashes(&new);
+    let mut root = merge(old, new, &Criteria::Auto, cfg);
+    detect_moves(&mut root, by_hash);
+    roll_up(&mut root);
+    root
+}
+
+/// Merges a left and a right tree into one, comparing the files by the given details
+///
+/// This is `diff` for two trees that are expected to be the same, e.g. build outputs
+/// and a staging directory: the left tree is the old one, so the entries only on the
+/// left are marked as removed and those only on the right as added, and no moves are
+/// detected.
+pub fn compare(left: TreeNode, right: TreeNode, by: &[CompareBy], cfg: &Config) -> TreeNode {
+    let mut root = merge(left, right, &Criteria::By(by), cfg);
+    roll_up(&mut root);
+    root
+}
+
+/// Counts the entries of a merged tree that are in the new tree, i.e. all the entries
+/// but the removed ones
+pub fn statistics(tree: &TreeNode) -> Statistics {
+    let stats = Statistics::default();
+    count(tree, &stats);
+    stats
+}
+
+/// Adds an entry of a merged tree, and its descendants, to the statistics
+fn count(node: &TreeNode, stats: &Statistics) {
+    if node
+        .diff
+        .as_ref()
+        .is_some_and(|diff| diff.status == DiffStatus::Removed)
+    {
+        return;
+    }
+    if node.error.is_some() {
+        stats.add_errors(1);
+    }
+    if matches!(node.node_type, NodeType::Directory) {
+        stats.add_dirs(1);
+        node.children.iter().for_each(|child| count(child, stats));
+    } else {
+        stats.add_files(1);
+        stats.add_byte_size(node.size.unwrap_or_default());
+    }
+}
+
+/// Merges two versions of an entry of the same kind (both directories, or neither)
+fn merge(old: TreeNode, mut new: TreeNode, criteria: &Criteria, cfg: &Config) -> TreeNode {
+    if matches!(new.node_type, NodeType::Directory) {
+        let children = mem::take(&mut new.children);
+        new.children = merge_children(old.children, children, &new.path, criteria, cfg);
+        new.diff = Some(Diff::new(DiffStatus::Unchanged));
+    } else {
+        let status = if differs(&old, &new, criteria) {
+            DiffStatus::Modified
+        } else {
+            DiffStatus::Unchanged
+        };
+        new.diff = Some(Diff {
+            size_delta: size(&new) - size(&old),
+            ..Diff::new(status)
+        });
+    }
+    new
+}
+
+/// Merges the entries of two versions of a directory, matching them by name
+///
+/// An entry that changed from a directory to something else (or the other way around)
+/// shows up twice, as removed and as added.
+fn merge_children(
+    old: Vec<TreeNode>,
+    new: Vec<TreeNode>,
+    dir: &Path,
+    criteria: &Criteria,
+    cfg: &Config,
+) -> Vec<TreeNode> {
+    let mut old: BTreeMap<String, TreeNode> = old
+        .into_iter()
+        .map(|node| (node.name.clone(), node))
+        .collect();
+    let mut merged = Vec::with_capacity(new.len().max(old.len()));
+    for node in new {
+        match old.remove(&node.name) {
+            Some(before) if is_dir(&before) == is_dir(&node) => {
+                merged.push(merge(before, node, criteria, cfg));
+            }
+            Some(before) => {
+                merged.push(mark(relocate(before, dir), DiffStatus::Removed));
+                merged.push(mark(node, DiffStatus::Added));
+            }
+            None => merged.push(mark(node, DiffStatus::Added)),
+        }
+    }
+    for node in old.into_values() {
+        merged.push(mark(relocate(node, dir), DiffStatus::Removed));
+    }
+    merged.sort_by(|a, b| sort::compare(a, b, cfg));
+    merged
+}
+
+/// Checks whether two versions of an entry that is not a directory differ
+fn differs(old: &TreeNode, new: &TreeNode, criteria: &Criteria) -> bool {
+    if mem::discriminant(&old.node_type) != mem::discriminant(&new.node_type)
+        || old.target != new.target
+    {
+        return true;
+    }
+    let differ_by = |by: CompareBy| match by {
+        CompareBy::Size => old.size != new.size,
+        // Snapshots only keep the modification times to the second
+        CompareBy::Mtime => {
+            let modified = |node: &TreeNode| {
+                let time = node.metadata.as_ref()?.modified?;
+                time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+            };
+            modified(old) != modified(new)
+        }
+        CompareBy::Hash => match (&old.hash, &new.hash) {
+            (Some(old), Some(new)) => old != new,
+            _ => old.size != new.size,
+        },
+    };
+    match criteria {
+        Criteria::Auto if old.hash.is_some() && new.hash.is_some() => differ_by(CompareBy::Hash),
+        Criteria::Auto => differ_by(CompareBy::Size) || differ_by(CompareBy::Mtime),
+        Criteria::By(by) => by.iter().any(|&by| differ_by(by)),
+    }
+}
+
+/// Marks an entry that is only in one of the trees, along with all of its descendants
+fn mark(mut node: TreeNode, status: DiffStatus) -> TreeNode {
+    let sign = if status == DiffStatus::Removed { -1 } else { 1 };
+    node.diff = Some(Diff {
+        size_delta: if is_dir(&node) { 0 } else { sign * size(&node) },
+        ..Diff::new(status)
+    });
+    node.children = node
+        .children
+        .into_iter()
+        .map(|child| mark(child, status))
+        .collect();
+    node
+}
+
+/// Moves an entry of the old tree, along with all of its descendants, into a directory
+/// of the merged tree
+fn relocate(mut node: TreeNode, dir: &Path) -> TreeNode {
+    node.path = dir.join(&node.name);
+    let path = node.path.clone();
+    node.children = node
+        .children
+        .into_iter()
+        .map(|child| relocate(child, &path))
+        .collect();
+    node
+}
+
+/// What a removed file and an added file must have in common to be considered a move
+#[derive(PartialEq, Eq, Hash)]
+enum MoveKey {
+    /// The same digest
+    Hash(String),
+    /// The same name and size, when the trees cannot be compared by digest
+    NameSize(String, u64),
+}
+
+impl MoveKey {
+    /// Returns the key of a file, or `None` for the other entries
+    fn of(node: &TreeNode, by_hash: bool) -> Option<Self> {
+        if !matches!(node.node_type, NodeType::File) {
+            return None;
+        }
+        if by_hash {
+            node.hash.clone().map(Self::Hash)
+        } else {
+            Some(Self::NameSize(node.name.clone(), node.size?))
+        }
+    }
+}
+
+/// Whether any file of a tree has a digest
+fn has_hashes(node: &TreeNode) -> bool {
+    node.hash.is_some() || node.children.iter().any(has_hashes)
+}
+
+/// Pairs up the removed and the added files of a merged tree as moves
+///
+/// The added file of each pair is marked as moved, and the removed one is dropped.
+fn detect_moves(root: &mut TreeNode, by_hash: bool) {
+    let mut removed: HashMap<MoveKey, Vec<PathBuf>> = HashMap::new();
+    let mut added = Vec::new();
+    collect_candidates(root, Path::new(""), by_hash, &mut removed, &mut added);
+
+    let mut moves = HashMap::new();
+    for (path, key) in added {
+        if let Some(from) = removed.get_mut(&key).and_then(Vec::pop) {
+            moves.insert(path, from);
+        }
+    }
+    if moves.is_empty() {
+        return;
+    }
+    let sources: HashSet<PathBuf> = moves.values().cloned().collect();
+    apply_moves(root, Path::new(""), &mut moves, &sources);
+}
+
+/// Collects the relative paths of the removed and the added files below a node
+fn collect_candidates(
+    node: &TreeNode,
+    relative: &Path,
+    by_hash: bool,
+    removed: &mut HashMap<MoveKey, Vec<PathBuf>>,
+    added: &mut Vec<(PathBuf, MoveKey)>,
+) {
+    for child in &node.children {
+        let path = relative.join(&child.name);
+        collect_candidates(child, &path, by_hash, removed, added);
+        let (Some(diff), Some(key)) = (&child.diff, MoveKey::of(child, by_hash)) else {
+            continue;
+        };
+        match diff.status {
+            DiffStatus::Removed => removed.entry(key).or_default().push(path),
+            DiffStatus::Added => added.push((path, key)),
+            _ => {}
+        }
+    }
+}
+
+/// Marks the destinations of the moves below a node, and drops their sources
+fn apply_moves(
+    node: &mut TreeNode,
+    relative: &Path,
+    moves: &mut HashMap<PathBuf, PathBuf>,
+    sources: &HashSet<PathBuf>,
+) {
+    node.children.retain(|child| {
+        let is_removed = child
+            .diff
+            .as_ref()
+            .is_some_and(|diff| diff.status == DiffStatus::Removed);
+        !(is_removed && !is_dir(child) && sources.contains(&relative.join(&child.name)))
+    });
+    for child in &mut node.children {
+        let path = relative.join(&child.name);
+        if let Some(from) = moves.remove(&path)
+            && let Some(diff) = &mut child.diff
+        {
+            diff.status = DiffStatus::Moved;
+            diff.size_delta = 0;
+            diff.moved_from = Some(from);
+        }
+        apply_moves(child, &path, moves, sources);
+    }
+}
+
+/// Sums the size deltas of the files below a directory into its own, and marks it as
+/// modified if any of its entries changed
+///
+/// Returns the size delta of the node.
+fn roll_up(node: &mut TreeNode) -> i64 {
+    if !is_dir(node) {
+        return node.diff.as_ref().map_or(0, |diff| diff.size_delta);
+    }
+    let delta = node.children.iter_mut().map(roll_up).sum();
+    let changed = node.children.iter().any(|child| {
+        child
+            .diff
+            .as_ref()
+            .is_some_and(|diff| diff.status != DiffStatus::Unchanged)
+    });
+    let diff = node
+        .diff
+        .get_or_insert_with(|| Diff::new(DiffStatus::Unchanged));
+    diff.size_delta = delta;
+    if changed && diff.status == DiffStatus::Unchanged {
+        diff.status = DiffStatus::Modified;
+    }
+    delta
+}
+
+/// Whether a node is a directory
+fn is_dir(node: &TreeNode) -> bool {
+    matches!(node.node_type, NodeType::Directory)
+}
+
+/// Returns the size of a node as a signed number, for computing deltas
+fn size(node: &TreeNode) -> i64 {
+    node.size
+        .map_or(0, |size| i64::try_from(size).unwrap_or(i64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use crate::hash::HashAlgorithm;
+
+    /// Builds the trees of two directories with the given files, and diffs them
+    fn diff_files(old: &[(&str, &str)], new: &[(&str, &str)], hash: bool) -> TreeNode {
+        let tree = |files: &[(&str, &str)]| {
+            let dir = tempfile::tempdir().unwrap();
+            for (path, contents) in files {
+                let path = dir.path().join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            }
+            let cfg = ConfigBuilder {
+                root: Some(dir.path().to_path_buf()),
+                size: true,
+                hash: hash.then_some(HashAlgorithm::Blake3),
+                ..Default::default()
+            }
+            .build();
+            let (tree, _) = crate::build(&cfg).unwrap();
+            (tree, cfg)
+        };
+        let (old, _) = tree(old);
+        let (new, cfg) = tree(new);
+        diff(old, new, &cfg)
+    }
+
+    /// Returns the node at a path relative to the root of a tree
+    fn find<'a>(tree: &'a TreeNode, path: &str) -> &'a TreeNode {
+        path.split('/').fold(tree, |node, name| {
+            let child = node.children.iter().find(|c| c.name == name);
+            child.unwrap_or_else(|| panic!("no {path}"))
+        })
+    }
+
+    fn diff_of<'a>(tree: &'a TreeNode, path: &str) -> &'a Diff {
+        find(tree, path).diff.as_ref().unwrap()
+    }
+
+    #[test]
+    fn moves_are_detected_by_digest() {
+        let tree = diff_files(&[("a/x.txt", "hello")], &[("b/y.txt", "hello")], true);
+        let moved = diff_of(&tree, "b/y.txt");
+        assert_eq!(moved.status, DiffStatus::Moved);
+        assert_eq!(moved.moved_from.as_deref(), Some(Path::new("a/x.txt")));
+        assert_eq!(moved.size_delta, 0);
+        // The source of the move is dropped, but not the directory it left
+        assert_eq!(diff_of(&tree, "a").status, DiffStatus::Removed);
+        assert!(find(&tree, "a").children.is_empty());
+
+        // With digests, the same name and size is not enough
+        let tree = diff_files(&[("a/x.txt", "hello")], &[("b/x.txt", "world")], true);
+        assert_eq!(diff_of(&tree, "b/x.txt").status, DiffStatus::Added);
+        assert_eq!(diff_of(&tree, "a/x.txt").status, DiffStatus::Removed);
+    }
+
+    #[test]
+    fn moves_are_detected_by_name_and_size_without_digests() {
+        let tree = diff_files(&[("a/x.txt", "hello")], &[("b/x.txt", "world")], false);
+        let moved = diff_of(&tree, "b/x.txt");
+        assert_eq!(moved.status, DiffStatus::Moved);
+        assert_eq!(moved.moved_from.as_deref(), Some(Path::new("a/x.txt")));
+
+        let tree = diff_files(&[("a/x.txt", "hello")], &[("b/x.txt", "hello!")], false);
+        assert_eq!(diff_of(&tree, "b/x.txt").status, DiffStatus::Added);
+    }
+
+    #[test]
+    fn changes_roll_up_into_the_directories() {
+        let old = [
+            ("d/keep.txt", "same"),
+            ("d/edit.txt", "1"),
+            ("d/gone.txt", "abc"),
+            ("u/file.txt", "same"),
+        ];
+        let new = [
+            ("d/keep.txt", "same"),
+            ("d/edit.txt", "12"),
+            ("d/new.txt", "wxyz"),
+            ("u/file.txt", "same"),
+        ];
+        let tree = diff_files(&old, &new, true);
+        let status = |path| diff_of(&tree, path).status;
+        assert_eq!(status("d/keep.txt"), DiffStatus::Unchanged);
+        assert_eq!(status("d/edit.txt"), DiffStatus::Modified);
+        assert_eq!(status("d/gone.txt"), DiffStatus::Removed);
+        assert_eq!(status("d/new.txt"), DiffStatus::Added);
+        assert_eq!(status("u"), DiffStatus::Unchanged);
+        assert_eq!(status("d"), DiffStatus::Modified);
+
+        assert_eq!(diff_of(&tree, "d").size_delta, 1 - 3 + 4);
+        assert_eq!(diff_of(&tree, "u").size_delta, 0);
+        let root = tree.diff.as_ref().unwrap();
+        assert_eq!((root.status, root.size_delta), (DiffStatus::Modified, 2));
+
+        let stats = statistics(&tree);
+        assert_eq!((stats.dirs(), stats.files()), (3, 4));
+    }
+}
@@ -0,0 +1,308 @@
+//! This module finds the files with identical contents in a tree, for `fstree dupes`.
+//!
+//! The files are narrowed down in stages, so that most of them are never read in full:
+//! first they are grouped by size, then the files of the same size by a digest of their
+//! first few kilobytes, and only then the files that are still alike by a digest of their
+//! whole contents. The digests are computed in parallel.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::hash::HashAlgorithm;
+use crate::helpers;
+use crate::tree::{NodeType, TreeNode};
+
+/// How many bytes at the start of a file the partial digest covers
+const PARTIAL_HASH_LEN: u64 = 4 * 1024;
+
+/// A set of files with identical contents
+#[derive(Clone, Debug, Serialize)]
+#[non_exhaustive]
+pub struct DuplicateGroup {
+    /// The number of the group, starting at 1, with the groups that waste the most
+    /// space first
+    pub id: usize,
+    /// The size of each file
+    pub size: u64,
+    /// The digest of the contents of each file
+    pub hash: String,
+    /// The number of bytes that would be freed by keeping only one of the files
+    pub reclaimable: u64,
+    /// The paths of the files, in the order they appear in the tree
+    pub paths: Vec<PathBuf>,
+}
+
+/// The duplicate group that a file of a tree belongs to (see `Duplicates::annotate`)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Duplicate {
+    /// The number of the group (see `DuplicateGroup::id`)
+    pub group: usize,
+    /// The number of bytes that would be freed by keeping only one file of the group
+    pub reclaimable: u64,
+}
+
+/// The groups of duplicate files found in a tree
+#[derive(Clone, Debug, Serialize)]
+#[non_exhaustive]
+pub struct Duplicates {
+    /// The algorithm of the digests
+    pub hash: HashAlgorithm,
+    /// The groups, with the groups that waste the most space first
+    pub groups: Vec<DuplicateGroup>,
+    /// The number of bytes that would be freed by keeping only one file of each group
+    pub reclaimable: u64,
+}
+
+/// A file that may have duplicates
+struct Candidate {
+    path: PathBuf,
+    size: u64,
+    /// The latest digest computed, partial or full
+    digest: String,
+}
+
+impl Duplicates {
+    /// Finds the files of a tree that have identical contents
+    ///
+    /// Only the files in the tree are considered, so the ignored and excluded files
+    /// are skipped like anywhere else. Empty files are skipped too, since there is
+    /// nothing to reclaim, and so are the extra hard links of a file, since they do
+    /// not take any space. A file that cannot be read is skipped, unless running in
+    /// strict mode.
+    pub fn find(tree: &TreeNode, algorithm: HashAlgorithm, cfg: &Config) -> io::Result<Self> {
+        let mut files = Vec::new();
+        collect_files(tree, &mut HashSet::new(), &mut files);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(cfg.threads.unwrap_or(0))
+            .build()
+            .map_err(io::Error::other)?;
+
+        // Files of a unique size cannot have duplicates, and are never read
+        let by_size = group_by(files, |file| file.size);
+
+        let hash = |file: &Candidate, len: u64| -> io::Result<Option<String>> {
+            match File::open(&file.path).and_then(|f| algorithm.hash_reader(f.take(len))) {
+                Ok(digest) => Ok(Some(digest)),
+                Err(e) if cfg.strict => Err(e),
+                Err(_) => Ok(None),
+            }
+        };
+        let by_partial = pool.install(|| refine(by_size, |file| hash(file, PARTIAL_HASH_LEN)))?;
+
+        // Files that are no larger than the partial digest are fully hashed by it already
+        let (small, large): (Vec<_>, Vec<_>) = by_partial
+            .into_iter()
+            .partition(|group| group[0].size <= PARTIAL_HASH_LEN);
+        let by_full = pool.install(|| refine(large, |file| hash(file, u64::MAX)))?;
+
+        let mut groups: Vec<_> = small
+            .into_iter()
+            .chain(by_full)
+            .map(|mut group| {
+                let size = group[0].size;
+                DuplicateGroup {
+                    id: 0,
+                    size,
+                    hash: mem::take(&mut group[0].digest),
+                    reclaimable: size * (group.len() as u64 - 1),
+                    paths: group.into_iter().map(|file| file.path).collect(),
+                }
+            })
+            .collect();
+
+        groups.sort_by(|a, b| {
+            b.reclaimable
+                .cmp(&a.reclaimable)
+                .then_with(|| a.paths.cmp(&b.paths))
+        });
+        for (i, group) in groups.iter_mut().enumerate() {
+            group.id = i + 1;
+        }
+        Ok(Self {
+            hash: algorithm,
+            reclaimable: groups.iter().map(|group| group.reclaimable).sum(),
+            groups,
+        })
+    }
+
+    /// Returns the number of files that have a duplicate
+    pub fn files(&self) -> usize {
+        self.groups.iter().map(|group| group.paths.len()).sum()
+    }
+
+    /// Describes the groups in a line, e.g. `2 duplicate groups, 5 files, 12B reclaimable`
+    pub fn summary(&self, cfg: &Config) -> String {
+        let groups = self.groups.len();
+        let plural = if groups == 1 { "" } else { "s" };
+        format!(
+            "{groups} duplicate group{plural}, {} files, {} reclaimable",
+            self.files(),
+            helpers::bytes::format(self.reclaimable, &cfg.size_format)
+        )
+    }
+
+    /// Keeps only the duplicate files of a tree, and the directories that contain them,
+    /// marking every file with its group and digest
+    ///
+    /// Returns `None` if there are no duplicates at all.
+    pub fn annotate(&self, mut tree: TreeNode) -> Option<TreeNode> {
+        let groups: HashMap<_, _> = self
+            .groups
+            .iter()
+            .flat_map(|group| group.paths.iter().map(move |path| (path, group)))
+            .collect();
+        retain(&mut tree, &groups).then_some(tree)
+    }
+}
+
+/// Collects the regular files of a tree that are not empty, and only one link of each
+/// file with several hard links
+fn collect_files(node: &TreeNode, seen: &mut HashSet<(u64, u64)>, files: &mut Vec<Candidate>) {
+    match node.node_type {
+        NodeType::Directory => {
+            for child in &node.children {
+                collect_files(child, seen, files);
+            }
+        }
+        NodeType::File if node.error.is_none() => {
+            let size = node.size.unwrap_or_default();
+            if size == 0 || node.hard_link.is_some_and(|id| !seen.insert(id)) {
+                return;
+            }
+            files.push(Candidate {
+                path: node.path.clone(),
+                size,
+                digest: String::new(),
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Groups items by a key, keeping only the groups of at least two items, in the order
+/// their first items came in
+fn group_by<T, K: Eq + std::hash::Hash>(items: Vec<T>, key: impl Fn(&T) -> K) -> Vec<Vec<T>> {
+    let mut indices: HashMap<K, usize> = HashMap::new();
+    let mut groups: Vec<Vec<T>> = Vec::new();
+    for item in items {
+        match indices.get(&key(&item)) {
+            Some(&i) => groups[i].push(item),
+            None => {
+                indices.insert(key(&item), groups.len());
+                groups.push(vec![item]);
+            }
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Splits groups of files further by a digest computed in parallel, leaving out the
+/// files without one and the files that turn out to be unique
+fn refine(
+    groups: Vec<Vec<Candidate>>,
+    digest: impl Fn(&Candidate) -> io::Result<Option<String>> + Sync,
+) -> io::Result<Vec<Vec<Candidate>>> {
+    let digested: Vec<Vec<Option<Candidate>>> = groups
+        .into_par_iter()
+        .map(|group| {
+            group
+                .into_par_iter()
+                .map(|file| {
+                    let digest = digest(&file)?;
+                    Ok(digest.map(|digest| Candidate { digest, ..file }))
+                })
+                .collect()
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(digested
+        .into_iter()
+        .flat_map(|group| {
+            group_by(group.into_iter().flatten().collect(), |file| {
+                file.digest.clone()
+            })
+        })
+        .collect())
+}
+
+/// Removes the entries of a directory that are neither duplicates nor contain any, and
+/// marks the duplicates
+///
+/// Returns whether the node itself is, or contains, a duplicate.
+fn retain(node: &mut TreeNode, groups: &HashMap<&PathBuf, &DuplicateGroup>) -> bool {
+    match node.node_type {
+        NodeType::Directory => {
+            node.children.retain_mut(|child| retain(child, groups));
+            !node.children.is_empty()
+        }
+        _ => match groups.get(&node.path) {
+            Some(group) => {
+                node.hash = Some(group.hash.clone());
+                node.duplicate = Some(Duplicate {
+                    group: group.id,
+                    reclaimable: group.reclaimable,
+                });
+                true
+            }
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    #[test]
+    fn groups_identical_files_and_skips_extra_hard_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &[u8]| {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+        };
+        write("a", b"same contents");
+        write("b", b"same contents");
+        write("c", b"same_contents");
+        write("empty", b"");
+        write("empty_too", b"");
+        // Larger files that only differ past the partial digest
+        let mut large = vec![b'x'; PARTIAL_HASH_LEN as usize * 2];
+        write("large", &large);
+        write("large_copy", &large);
+        *large.last_mut().unwrap() = b'y';
+        write("large_other", &large);
+        #[cfg(unix)]
+        std::fs::hard_link(dir.path().join("a"), dir.path().join("a_link")).unwrap();
+
+        let cfg = ConfigBuilder {
+            root: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        }
+        .build();
+        let (tree, _) = crate::build(&cfg).unwrap();
+        let duplicates = Duplicates::find(&tree, HashAlgorithm::Blake3, &cfg).unwrap();
+
+        let names = |group: &DuplicateGroup| -> Vec<String> {
+            let name = |path: &PathBuf| path.file_name().unwrap().to_string_lossy().to_string();
+            group.paths.iter().map(name).collect()
+        };
+        assert_eq!(duplicates.groups.len(), 2);
+        let (large, small) = (&duplicates.groups[0], &duplicates.groups[1]);
+        assert_eq!(names(large), ["large", "large_copy"]);
+        assert_eq!(large.reclaimable, PARTIAL_HASH_LEN * 2);
+        // Only one of the links of `a` is kept, whichever comes first in the tree
+        assert_eq!(names(small), ["a", "b"]);
+        assert_eq!(small.reclaimable, 13);
+        assert_eq!((large.id, small.id), (1, 2));
+        assert_eq!(duplicates.reclaimable, PARTIAL_HASH_LEN * 2 + 13);
+    }
+}
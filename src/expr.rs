@@ -0,0 +1,684 @@
+//! This module implements the filter expression language of `--where`.
+//!
+//! An expression combines tests on the fields of an entry with `and`, `or`, `not` and
+//! parentheses, e.g. `(ext == rs and size > 10KB) or name ~ 'README*'`.
+//!
+//! | Field   | Value                                                | Operators                     |
+//! | ------- | ---------------------------------------------------- | ----------------------------- |
+//! | `name`  | The file name                                        | `== != < <= > >= ~ =~`        |
+//! | `path`  | The path relative to the root, with `/` separators   | `== != < <= > >= ~ =~`        |
+//! | `ext`   | The extension, without the dot (empty if none)       | `== != < <= > >= ~ =~`        |
+//! | `size`  | The size, with an optional unit (e.g. `10KB`)        | `== != < <= > >=`             |
+//! | `mtime` | A date, a duration ago (e.g. `2d`) or a file         | `== != < <= > >=`             |
+//! | `depth` | The depth below the root (`1` for its entries)       | `== != < <= > >=`             |
+//! | `type`  | `f`, `d`, `l`, `x`, `p` or `s` (see `--type`)        | `== !=`                       |
+//! | `perm`  | Permission bits, e.g. `644`, `-644` or `/111`        | `== !=`                       |
+//!
+//! `~` matches a glob and `=~` a regular expression. Values can be quoted with `'` or `"`,
+//! and must be when they contain spaces, parentheses or operator characters.
+
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+
+use crate::helpers;
+use crate::helpers::time::PointInTime;
+use crate::predicate::{FileKind, PermMatch};
+use crate::source::Stat;
+
+/// A parsed filter expression
+#[derive(Clone, Debug)]
+pub enum Expr {
+    /// Both expressions hold
+    And(Box<Expr>, Box<Expr>),
+    /// Either expression holds
+    Or(Box<Expr>, Box<Expr>),
+    /// The expression does not hold
+    Not(Box<Expr>),
+    /// A test on a single field
+    Test(Test),
+}
+
+/// A test on a single field of an entry
+#[derive(Clone, Debug)]
+pub enum Test {
+    /// Compares a text field with a string
+    Text(TextField, Comparison, String),
+    /// Matches a text field against a glob
+    Glob(TextField, GlobMatcher),
+    /// Matches a text field against a regular expression
+    Regex(TextField, Regex),
+    /// Compares the size with a number of bytes
+    Size(Comparison, u64),
+    /// Compares the modification time with a point in time
+    Mtime(Comparison, SystemTime),
+    /// Compares the depth with a number
+    Depth(Comparison, usize),
+    /// Checks the kind of entry (or, if negated, that it is not of that kind)
+    Type(bool, FileKind),
+    /// Checks the permission bits (or, if negated, that they fail the test)
+    Perm(bool, PermMatch),
+}
+
+/// The text fields of an entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextField {
+    Name,
+    Path,
+    Ext,
+}
+
+/// A comparison operator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    /// Checks whether `left` and `right` compare as required by the operator
+    fn holds<T: PartialOrd>(self, left: &T, right: &T) -> bool {
+        match self {
+            Self::Eq => left == right,
+            Self::Ne => left != right,
+            Self::Lt => left < right,
+            Self::Le => left <= right,
+            Self::Gt => left > right,
+            Self::Ge => left >= right,
+        }
+    }
+}
+
+/// The fields of an entry that an expression is evaluated against
+pub struct Entry<'a> {
+    /// The path relative to the root
+    pub path: &'a Path,
+    /// The (non-followed) metadata, if it could be read
+    pub metadata: Option<&'a dyn Stat>,
+}
+
+impl Expr {
+    /// Parses an expression, resolving relative times against the current time
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let tokens = lex(source)?;
+        let mut parser = Parser {
+            source,
+            tokens,
+            position: 0,
+            now: SystemTime::now(),
+        };
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(parser.error(token, "expected `and`, `or` or the end")),
+        }
+    }
+
+    /// Evaluates the expression for an entry
+    ///
+    /// Tests on metadata fail when the metadata could not be read.
+    pub fn matches(&self, entry: &Entry) -> bool {
+        match self {
+            Self::And(left, right) => left.matches(entry) && right.matches(entry),
+            Self::Or(left, right) => left.matches(entry) || right.matches(entry),
+            Self::Not(expr) => !expr.matches(entry),
+            Self::Test(test) => test.matches(entry),
+        }
+    }
+}
+
+impl Test {
+    /// Evaluates the test for an entry
+    fn matches(&self, entry: &Entry) -> bool {
+        match self {
+            Self::Text(field, op, value) => op.holds(&field.get(entry).as_str(), &value.as_str()),
+            Self::Glob(field, glob) => glob.is_match(field.get(entry)),
+            Self::Regex(field, regex) => regex.is_match(&field.get(entry)),
+            Self::Size(op, size) => entry.metadata.is_some_and(|m| op.holds(&m.size(), size)),
+            Self::Mtime(op, time) => entry
+                .metadata
+                .and_then(|m| m.mtime())
+                .is_some_and(|modified| op.holds(&modified, time)),
+            Self::Depth(op, depth) => op.holds(&entry.path.components().count(), depth),
+            Self::Type(eq, kind) => entry.metadata.is_some_and(|m| kind.matches(m) == *eq),
+            Self::Perm(eq, perm) => entry
+                .metadata
+                .and_then(|m| m.mode())
+                .is_some_and(|mode| perm.matches(mode) == *eq),
+        }
+    }
+}
+
+impl TextField {
+    /// Returns the value of the field for an entry
+    fn get(self, entry: &Entry) -> String {
+        let value = match self {
+            Self::Name => entry.path.file_name(),
+            Self::Path => Some(entry.path.as_os_str()),
+            Self::Ext => entry.path.extension(),
+        };
+        let value = value.map(|v| v.to_string_lossy()).unwrap_or_default();
+        match self {
+            Self::Path => value.replace(std::path::MAIN_SEPARATOR, "/"),
+            _ => value.into_owned(),
+        }
+    }
+}
+
+/// An error in an expression, pointing at the offending part of it
+#[derive(Debug)]
+pub struct ParseError {
+    /// The expression that failed to parse
+    source: String,
+    /// What went wrong
+    message: String,
+    /// The byte range of the offending part of the expression
+    span: (usize, usize),
+}
+
+impl fmt::Display for ParseError {
+    /// Shows the message, then the expression with a caret under the offending part
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = self.span;
+        let indent = self.source[..start].chars().count();
+        let width = self.source[start..end].chars().count().max(1);
+        writeln!(f, "invalid expression: {}", self.message)?;
+        writeln!(f, "  {}", self.source)?;
+        write!(f, "  {}{}", " ".repeat(indent), "^".repeat(width))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The kinds of tokens of the language
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    Op(&'static str),
+    /// A bare word, which may be a keyword, a field or a value
+    Word(String),
+    /// A quoted string, which is always a value
+    Quoted(String),
+}
+
+/// A token, along with its byte range in the expression
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    span: (usize, usize),
+}
+
+/// The operators, longest first so that e.g. `<=` is not read as `<`
+const OPERATORS: [&str; 8] = ["==", "!=", "<=", ">=", "=~", "<", ">", "~"];
+
+/// Splits an expression into tokens
+fn lex(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let kind = if c == '(' || c == ')' {
+            chars.next();
+            if c == '(' {
+                TokenKind::LParen
+            } else {
+                TokenKind::RParen
+            }
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, q)) if q == c => break,
+                    Some((_, ch)) => value.push(ch),
+                    None => {
+                        return Err(ParseError {
+                            source: source.to_string(),
+                            message: "unterminated string".to_string(),
+                            span: (start, source.len()),
+                        });
+                    }
+                }
+            }
+            TokenKind::Quoted(value)
+        } else if let Some(op) = OPERATORS
+            .iter()
+            .find(|op| source[start..].starts_with(**op))
+        {
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            TokenKind::Op(op)
+        } else if "=!".contains(c) {
+            return Err(ParseError {
+                source: source.to_string(),
+                message: format!("unknown operator `{c}`"),
+                span: (start, start + c.len_utf8()),
+            });
+        } else {
+            let mut word = String::new();
+            while let Some(&(_, ch)) = chars.peek() {
+                if ch.is_whitespace() || "()'\"=!<>~".contains(ch) {
+                    break;
+                }
+                word.push(ch);
+                chars.next();
+            }
+            TokenKind::Word(word)
+        };
+
+        let end = chars.peek().map_or(source.len(), |&(i, _)| i);
+        tokens.push(Token {
+            kind,
+            span: (start, end),
+        });
+    }
+    Ok(tokens)
+}
+
+/// A recursive-descent parser over the tokens of an expression
+///
+/// ```text
+/// or      := and ("or" and)*
+/// and     := not ("and" not)*
+/// not     := "not" not | primary
+/// primary := "(" or ")" | field operator value
+/// ```
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    /// The time that durations are relative to
+    now: SystemTime,
+}
+
+impl Parser<'_> {
+    /// Returns the next token, without consuming it
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Consumes the next token
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consumes the next token if it is the given keyword (case-insensitive)
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(
+            self.peek(),
+            Some(Token { kind: TokenKind::Word(word), .. }) if word.eq_ignore_ascii_case(keyword)
+        );
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// Creates an error pointing at a token
+    fn error(&self, token: &Token, message: &str) -> ParseError {
+        ParseError {
+            source: self.source.to_string(),
+            message: message.to_string(),
+            span: token.span,
+        }
+    }
+
+    /// Creates an error pointing at the end of the expression
+    fn error_at_end(&self, message: &str) -> ParseError {
+        let end = self.source.len();
+        ParseError {
+            source: self.source.to_string(),
+            message: message.to_string(),
+            span: (end, end),
+        }
+    }
+
+    /// Consumes the next token, failing with the given message at the end of the expression
+    fn expect(&mut self, message: &str) -> Result<Token, ParseError> {
+        self.next().ok_or_else(|| self.error_at_end(message))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self.expect("expected a test, e.g. `size > 10KB`")?;
+        match &token.kind {
+            TokenKind::LParen => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::RParen,
+                        ..
+                    }) => Ok(expr),
+                    Some(other) => Err(self.error(&other, "expected `)`")),
+                    None => Err(self.error(&token, "unclosed `(`")),
+                }
+            }
+            TokenKind::Word(field) => {
+                let field = field.to_lowercase();
+                let op = self.expect("expected an operator, e.g. `==`")?;
+                let TokenKind::Op(op_text) = op.kind else {
+                    return Err(self.error(&op, "expected an operator, e.g. `==`"));
+                };
+                let value = self.expect("expected a value")?;
+                let text = match &value.kind {
+                    TokenKind::Word(text) | TokenKind::Quoted(text) => text.clone(),
+                    _ => return Err(self.error(&value, "expected a value")),
+                };
+                self.parse_test(&token, &field, &op, op_text, &value, &text)
+                    .map(Expr::Test)
+            }
+            _ => Err(self.error(&token, "expected a field, e.g. `name` or `size`")),
+        }
+    }
+
+    /// Builds a test from its field, operator and value
+    fn parse_test(
+        &self,
+        field_token: &Token,
+        field: &str,
+        op_token: &Token,
+        op: &str,
+        value_token: &Token,
+        value: &str,
+    ) -> Result<Test, ParseError> {
+        let ordering = match op {
+            "==" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            "<" => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            _ => None,
+        };
+        let bad_op = |expected: &str| {
+            self.error(
+                op_token,
+                &format!("`{op}` cannot be used with `{field}` (expected {expected})"),
+            )
+        };
+        let bad_value = |message: String| self.error(value_token, &message);
+
+        let text_field = match field {
+            "name" => Some(TextField::Name),
+            "path" => Some(TextField::Path),
+            "ext" | "extension" => Some(TextField::Ext),
+            _ => None,
+        };
+        if let Some(text_field) = text_field {
+            return match (op, ordering) {
+                ("~", _) => Glob::new(value)
+                    .map(|glob| Test::Glob(text_field, glob.compile_matcher()))
+                    .map_err(|e| bad_value(e.kind().to_string())),
+                ("=~", _) => Regex::new(value)
+                    .map(|regex| Test::Regex(text_field, regex))
+                    .map_err(|e| bad_value(regex_message(&e))),
+                (_, Some(ordering)) => Ok(Test::Text(text_field, ordering, value.to_string())),
+                _ => unreachable!("every operator is either a comparison, `~` or `=~`"),
+            };
+        }
+
+        match field {
+            "size" => {
+                let ordering = ordering.ok_or_else(|| bad_op("a comparison"))?;
+                helpers::bytes::parse(value)
+                    .map(|size| Test::Size(ordering, size))
+                    .map_err(bad_value)
+            }
+            "mtime" => {
+                let ordering = ordering.ok_or_else(|| bad_op("a comparison"))?;
+                let time = value.parse::<PointInTime>().map_err(bad_value)?;
+                time.resolve(self.now)
+                    .map(|time| Test::Mtime(ordering, time))
+                    .map_err(|e| bad_value(e.to_string()))
+            }
+            "depth" => {
+                let ordering = ordering.ok_or_else(|| bad_op("a comparison"))?;
+                value
+                    .parse()
+                    .map(|depth| Test::Depth(ordering, depth))
+                    .map_err(|_| bad_value(format!("invalid depth: {value}")))
+            }
+            "type" | "perm" | "perms" => {
+                let eq = match ordering {
+                    Some(Comparison::Eq) => true,
+                    Some(Comparison::Ne) => false,
+                    _ => return Err(bad_op("`==` or `!=`")),
+                };
+                if field == "type" {
+                    value.parse().map(|kind| Test::Type(eq, kind))
+                } else {
+                    value.parse().map(|perm| Test::Perm(eq, perm))
+                }
+                .map_err(bad_value)
+            }
+            _ => Err(self.error(
+                field_token,
+                &format!(
+                    "unknown field `{field}` (expected name, path, ext, size, mtime, depth, type or perm)"
+                ),
+            )),
+        }
+    }
+}
+
+/// Returns the last line of a regex error, which describes the problem
+///
+/// The full message has its own caret diagram, which would point within the value only.
+fn regex_message(error: &regex::Error) -> String {
+    let message = error.to_string();
+    let last = message.lines().last().unwrap_or_default();
+    last.strip_prefix("error: ").unwrap_or(last).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shows the structure of an expression, with the values of its text tests as leaves
+    fn shape(expr: &Expr) -> String {
+        match expr {
+            Expr::And(left, right) => format!("({} and {})", shape(left), shape(right)),
+            Expr::Or(left, right) => format!("({} or {})", shape(left), shape(right)),
+            Expr::Not(expr) => format!("not {}", shape(expr)),
+            Expr::Test(Test::Text(_, _, value)) => value.clone(),
+            Expr::Test(test) => format!("{test:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_with_precedence() {
+        let cases = [
+            ("name == a", "a"),
+            ("name == a or name == b and name == c", "(a or (b and c))"),
+            ("name == a and name == b or name == c", "((a and b) or c)"),
+            ("(name == a or name == b) and name == c", "((a or b) and c)"),
+            ("name == a or name == b or name == c", "((a or b) or c)"),
+            ("not name == a and name == b", "(not a and b)"),
+            ("not (name == a and name == b)", "not (a and b)"),
+            ("not not name == a", "not not a"),
+            (
+                "NOT name == a AND name == b OR name == c",
+                "((not a and b) or c)",
+            ),
+            ("((name == a))", "a"),
+        ];
+        for (source, expected) in cases {
+            let expr = Expr::parse(source).unwrap_or_else(|e| panic!("{source}: {e}"));
+            assert_eq!(shape(&expr), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let cases = [
+            ("name == 'a b'", "a b"),
+            ("name == \"a b\"", "a b"),
+            ("name == 'and'", "and"),
+            ("name == \"it's\"", "it's"),
+            ("name == 'x(y) == z'", "x(y) == z"),
+            ("name==''", ""),
+            ("name=='a'", "a"),
+        ];
+        for (source, expected) in cases {
+            let expr = Expr::parse(source).unwrap_or_else(|e| panic!("{source}: {e}"));
+            assert_eq!(shape(&expr), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn matches_the_text_fields_of_entries() {
+        let cases = [
+            ("name == main.rs", true),
+            ("name != main.rs", false),
+            ("name < n", true),
+            ("ext == rs", true),
+            ("ext == ''", false),
+            ("path == src/main.rs", true),
+            ("name ~ '*.rs'", true),
+            ("name ~ '*.txt'", false),
+            ("path =~ '^src/'", true),
+            ("path =~ '^lib/'", false),
+            ("depth == 2", true),
+            ("depth > 2", false),
+            ("name == main.rs and not depth > 1", false),
+            ("name == lib.rs or depth >= 2", true),
+            // Tests on metadata fail when it is missing
+            ("size > 0", false),
+            ("not size > 0", true),
+            ("type == f", false),
+        ];
+        let entry = Entry {
+            path: Path::new("src/main.rs"),
+            metadata: None,
+        };
+        for (source, expected) in cases {
+            let expr = Expr::parse(source).unwrap_or_else(|e| panic!("{source}: {e}"));
+            assert_eq!(expr.matches(&entry), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn parses_the_values_of_the_other_fields() {
+        let parse = |source: &str| match Expr::parse(source) {
+            Ok(Expr::Test(test)) => test,
+            other => panic!("{source}: {other:?}"),
+        };
+        assert!(matches!(
+            parse("size > 10KB"),
+            Test::Size(Comparison::Gt, 10_240)
+        ));
+        assert!(matches!(
+            parse("size <= '1.5 MB'"),
+            Test::Size(Comparison::Le, 1_572_864)
+        ));
+        assert!(matches!(
+            parse("depth != 3"),
+            Test::Depth(Comparison::Ne, 3)
+        ));
+        assert!(matches!(
+            parse("mtime < 2d"),
+            Test::Mtime(Comparison::Lt, _)
+        ));
+        assert!(matches!(
+            parse("type == d"),
+            Test::Type(true, FileKind::Directory)
+        ));
+        assert!(matches!(
+            parse("type != l"),
+            Test::Type(false, FileKind::Symlink)
+        ));
+        assert!(matches!(parse("perm == 644"), Test::Perm(true, _)));
+        assert!(matches!(
+            parse("EXTENSION ~ r*"),
+            Test::Glob(TextField::Ext, _)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        // The source, part of the message, and the part of the source it points at
+        let cases = [
+            ("", "expected a test", ""),
+            ("name", "expected an operator", ""),
+            ("name ==", "expected a value", ""),
+            ("name == a or", "expected a test", ""),
+            ("name == a b", "expected `and`, `or` or the end", "b"),
+            ("name == (", "expected a value", "("),
+            ("name a", "expected an operator", "a"),
+            ("== a", "expected a field", "=="),
+            (") name == a", "expected a field", ")"),
+            ("name == 'a", "unterminated string", "'a"),
+            ("name = a", "unknown operator `=`", "="),
+            ("name ! a", "unknown operator `!`", "!"),
+            ("(name == a", "unclosed `(`", "("),
+            ("(name == a name", "expected `)`", "name"),
+            ("colour == red", "unknown field `colour`", "colour"),
+            ("size ~ 10", "`~` cannot be used with `size`", "~"),
+            ("size > ten", "Invalid size", "ten"),
+            ("depth > -1", "invalid depth", "-1"),
+            ("type < f", "`<` cannot be used with `type`", "<"),
+            ("type == q", "", "q"),
+            ("perm == 999", "", "999"),
+            ("mtime > someday", "Invalid point in time", "someday"),
+            ("name =~ '('", "", "'('"),
+            ("name ~ '[a'", "", "'[a'"),
+        ];
+        for (source, message, span) in cases {
+            let Err(error) = Expr::parse(source) else {
+                panic!("{source}: expected an error");
+            };
+            assert!(
+                error.message.contains(message),
+                "{source}: {}",
+                error.message
+            );
+            assert_eq!(&source[error.span.0..error.span.1], span, "{source}");
+        }
+    }
+
+    #[test]
+    fn points_at_the_offending_part_of_the_expression() {
+        let error = Expr::parse("size ~ 10").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid expression: `~` cannot be used with `size` (expected a comparison)\n  size ~ 10\n       ^"
+        );
+    }
+}
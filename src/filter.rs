@@ -1,9 +1,15 @@
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use globset::{Glob, GlobMatcher};
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{RegexBuilder, RegexSet, RegexSetBuilder};
 
 use crate::config::Config;
+use crate::expr::{self, Expr};
+use crate::gitignore::IgnoreRules;
+use crate::predicate::Predicates;
+use crate::source::Stat;
+use crate::stats::Statistics;
 
 /// A filter for file system entries.
 ///
@@ -11,107 +17,417 @@ use crate::config::Config;
 /// included in the output, based on the user's configuration.
 pub struct FileFilter {
     root: PathBuf,
+    /// The absolute path of the root, which the gitignore rules are matched against
+    absolute_root: PathBuf,
     only_directories: bool,
-    show_all: bool,
-    include_pattern: Option<GlobMatcher>,
-    exclude_pattern: Option<GlobMatcher>,
-    ignorer: Gitignore,
+    /// Whether to show hidden entries
+    hidden: bool,
+    /// Whether to honor git's ignore files
+    vcs_ignore: bool,
+    include_patterns: Option<PatternSet>,
+    exclude_patterns: Option<PatternSet>,
+    /// Whether the exclude patterns apply to directories too
+    exclude_dirs: bool,
+    regex_include: Option<RegexSet>,
+    regex_exclude: Option<RegexSet>,
+    /// Whether the regular expressions match the relative path, instead of the name
+    regex_path: bool,
+    /// The metadata-based filters, if any
+    predicates: Option<Predicates>,
+    /// The filter expression, if any
+    expression: Option<Expr>,
+    /// The gitignore rules that apply above the root
+    base_rules: Arc<IgnoreRules>,
+    /// Custom ignore files, which apply to the whole tree (none with `no_ignore`)
+    ignore_files: Vec<PathBuf>,
 }
 
 impl FileFilter {
     /// Creates a new `FileFilter` with the given configuration.
     pub fn new(cfg: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let absolute_root = cfg
+            .root
+            .canonicalize()
+            .or_else(|_| std::path::absolute(&cfg.root))?;
+        let vcs_ignore = !cfg.no_ignore && !cfg.no_ignore_vcs;
+        let base_rules = if vcs_ignore {
+            IgnoreRules::for_root(&absolute_root, !cfg.no_ignore_parent, !cfg.no_ignore_global)
+        } else {
+            IgnoreRules::empty()
+        };
+        let ignore_files = if cfg.no_ignore {
+            Vec::new()
+        } else {
+            cfg.ignore.iter().map(|f| absolute_root.join(f)).collect()
+        };
         Ok(Self {
             root: cfg.root.clone(),
+            absolute_root,
             only_directories: cfg.directory,
-            show_all: cfg.show_all,
-            include_pattern: Self::compile_glob(&cfg.include)?,
-            exclude_pattern: Self::compile_glob(&cfg.exclude)?,
-            ignorer: Self::setup_gitignore(&cfg.root, &cfg.ignore)?,
+            hidden: cfg.hidden,
+            vcs_ignore,
+            ignore_files,
+            include_patterns: PatternSet::new(&Self::include_patterns(cfg)?)?,
+            exclude_patterns: PatternSet::new(&cfg.exclude)?,
+            exclude_dirs: cfg.exclude_dirs,
+            regex_include: Self::compile_regexes(&cfg.regex_include, cfg.regex_ignore_case)?,
+            regex_exclude: Self::compile_regexes(&cfg.regex_exclude, cfg.regex_ignore_case)?,
+            regex_path: cfg.regex_path,
+            predicates: Predicates::new(cfg)?,
+            expression: cfg.expression.as_deref().map(Expr::parse).transpose()?,
+            base_rules,
         })
     }
 
-    /// Compiles a glob pattern into a `GlobMatcher`
-    fn compile_glob(pattern: &Option<String>) -> Result<Option<GlobMatcher>, globset::Error> {
-        pattern
-            .as_ref()
-            .map(|pat| Glob::new(pat))
-            .transpose()
-            .map(|g| g.map(|glob| glob.compile_matcher()))
+    /// Returns the include patterns, followed by those of the pattern file
+    ///
+    /// Blank lines and lines starting with `#` are skipped.
+    fn include_patterns(cfg: &Config) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut patterns = cfg.include.clone();
+        if let Some(path) = &cfg.pattern_file {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read the pattern file {}: {e}", path.display()))?;
+            patterns.extend(
+                content
+                    .lines()
+                    .map(str::trim_end)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        Ok(patterns)
     }
 
-    /// Filters a directory's entries, returning a vector of included entries.
-    pub fn filter_entries(&self, path: &Path) -> std::io::Result<Vec<std::fs::DirEntry>> {
-        Ok(std::fs::read_dir(path)?
-            .filter_map(Result::ok)
-            .filter(|entry| self.should_include(entry))
-            .collect())
+    /// Compiles regular expressions into a `RegexSet`, returning `None` if there are none
+    ///
+    /// Every pattern is first compiled on its own, so that an error points to the bad pattern
+    /// (and to the position of the problem within it).
+    fn compile_regexes(patterns: &[String], ignore_case: bool) -> Result<Option<RegexSet>, String> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        for pattern in patterns {
+            RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| format!("Invalid regular expression `{pattern}`:\n{e}"))?;
+        }
+        RegexSetBuilder::new(patterns)
+            .case_insensitive(ignore_case)
+            .build()
+            .map(Some)
+            .map_err(|e| e.to_string())
     }
 
-    /// Sets up gitignore handling for the given root path
-    fn setup_gitignore(root: &Path, ignore_files: &[String]) -> Result<Gitignore, ignore::Error> {
-        // Instantiate the ignore::GitignoreBuilder
-        let mut builder = GitignoreBuilder::new(root);
+    /// Filters a directory's entries, returning a vector of included entries.
+    ///
+    /// `rules`: The gitignore rules for the entries of the directory (see `ignore_rules`)
+    ///
+    /// Entries that could not be read are kept as errors, so that they can be reported.
+    /// The entries that are left out are counted in the statistics.
+    pub fn filter_entries(
+        &self,
+        path: &Path,
+        rules: &IgnoreRules,
+        stats: &Statistics,
+    ) -> std::io::Result<Vec<std::io::Result<std::fs::DirEntry>>> {
+        let mut filtered = 0;
+        let entries = std::fs::read_dir(path)?
+            .filter(|entry| match entry {
+                Ok(entry) => {
+                    let include = self.should_include(entry, rules);
+                    filtered += usize::from(!include);
+                    include
+                }
+                Err(_) => true,
+            })
+            .collect();
+        stats.add_filtered(filtered);
+        Ok(entries)
+    }
 
-        // Ignore the .git folder
-        builder.add_line(None, ".git")?;
+    /// Returns the gitignore rules for the entries of a directory
+    ///
+    /// These are the rules of its parent (`None` for the root), along with the patterns
+    /// of the directory's own `.gitignore` file. The custom ignore files are added at the root.
+    pub fn ignore_rules(&self, dir: &Path, parent: Option<&Arc<IgnoreRules>>) -> Arc<IgnoreRules> {
+        let parent = parent.unwrap_or(&self.base_rules);
+        let dir = self.absolute(dir);
 
-        // Add the project's .gitignore file if it exists
-        let gitignore_path = root.join(".gitignore");
-        if gitignore_path.exists() {
-            builder.add(gitignore_path);
+        let mut files = Vec::new();
+        if self.vcs_ignore {
+            files.push(dir.join(".gitignore"));
         }
-
-        // Add custom ignore files
-        for ignore in ignore_files {
-            let path = root.join(ignore);
-            if path.exists() {
-                builder.add(path);
-            }
+        if dir == self.absolute_root {
+            files.extend(self.ignore_files.iter().cloned());
+        }
+        if files.is_empty() {
+            return Arc::clone(parent);
         }
+        parent.push(&dir, &files)
+    }
 
-        // Build the gitignore handler
-        builder.build()
+    /// Converts a path within the tree to an absolute path
+    fn absolute(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => self.absolute_root.clone(),
+            Ok(relative) => self.absolute_root.join(relative),
+            Err(_) => path.to_path_buf(),
+        }
     }
 
     /// Checks if a given directory entry should be included in the output.
-    fn should_include(&self, entry: &std::fs::DirEntry) -> bool {
+    fn should_include(&self, entry: &std::fs::DirEntry, rules: &IgnoreRules) -> bool {
         let file_type = match entry.file_type() {
             Ok(ft) => ft,
-            Err(_) => return false,
+            Err(_) => return true, // Keep the entry, so that the error can be reported
         };
 
         let is_dir = file_type.is_dir();
         let file_name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        let relative_path = path.strip_prefix(&self.root).unwrap_or(&path);
+        if !self.matches(relative_path, &file_name, is_dir, || entry.metadata().ok()) {
+            return false;
+        }
+
+        // Ignore files filter, which always hides the .git folder when honoring git's ignore files
+        if (self.vcs_ignore && file_name == ".git")
+            || rules.is_ignored(&self.absolute(&path), is_dir)
+        {
+            return false;
+        }
 
+        true
+    }
+
+    /// Checks whether an entry that is not read from the file system should be included in
+    /// the output, e.g. a deleted file (see `Config::changed`) or an entry of a git tree
+    ///
+    /// The ignore files do not apply. Without metadata, the entry passes the filters that
+    /// depend on it.
+    pub fn should_include_path(
+        &self,
+        relative_path: &Path,
+        is_dir: bool,
+        stat: Option<&dyn Stat>,
+    ) -> bool {
+        let file_name = relative_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.matches(relative_path, &file_name, is_dir, || stat)
+    }
+
+    /// Checks an entry against all the filters, except for the ignore files
+    ///
+    /// `metadata`: Looks up the metadata of the entry, only if a filter needs it
+    fn matches<S: Stat>(
+        &self,
+        relative_path: &Path,
+        file_name: &str,
+        is_dir: bool,
+        metadata: impl FnOnce() -> Option<S>,
+    ) -> bool {
         // Directory-only filter
         if self.only_directories && !is_dir {
             return false;
         }
 
-        // Include pattern filter (skip directories)
-        if let Some(pattern) = &self.include_pattern {
-            if !is_dir && !pattern.is_match(&file_name) {
-                return false;
-            }
+        // Include and exclude pattern filters, matched against the path relative to the root
+        // (skip directories, unless the exclude patterns should hide them)
+        if let Some(patterns) = &self.include_patterns
+            && !is_dir
+            && patterns.matches(relative_path) != Some(true)
+        {
+            return false;
+        }
+        if let Some(patterns) = &self.exclude_patterns
+            && (!is_dir || self.exclude_dirs)
+            && patterns.matches(relative_path) == Some(true)
+        {
+            return false;
         }
 
-        // Exclude pattern filter (skip directories)
-        if let Some(pattern) = &self.exclude_pattern {
-            if !is_dir && pattern.is_match(&file_name) {
-                return false;
-            }
+        // Regular expression filters, matched against the name or the relative path
+        let subject = if self.regex_path {
+            relative_path.to_string_lossy()
+        } else {
+            file_name.into()
+        };
+        if let Some(regexes) = &self.regex_include
+            && !is_dir
+            && !regexes.is_match(&subject)
+        {
+            return false;
+        }
+        if let Some(regexes) = &self.regex_exclude
+            && (!is_dir || self.exclude_dirs)
+            && regexes.is_match(&subject)
+        {
+            return false;
         }
 
-        // Gitignore filter
-        if !self.show_all {
-            if let Ok(rel_path) = entry.path().strip_prefix(&self.root) {
-                if self.ignorer.matched(rel_path, is_dir).is_ignore() {
+        // Metadata filters and the filter expression (skip directories), which cost an
+        // extra lookup
+        if !is_dir && (self.predicates.is_some() || self.expression.is_some()) {
+            let metadata = metadata();
+            if let Some(predicates) = &self.predicates
+                && let Some(metadata) = &metadata
+                && !predicates.matches(metadata)
+            {
+                return false;
+            }
+            if let Some(expression) = &self.expression {
+                let entry = expr::Entry {
+                    path: relative_path,
+                    metadata: metadata.as_ref().map(|m| m as &dyn Stat),
+                };
+                if !expression.matches(&entry) {
                     return false;
                 }
             }
         }
 
-        true
+        // Hidden filter
+        self.hidden || !file_name.starts_with('.')
+    }
+}
+
+/// An ordered list of glob patterns, where the last pattern that matches a path wins
+///
+/// Like in gitignore files, a pattern starting with `!` is negated, and a pattern without
+/// a `/` matches the file name at any depth. The other patterns are matched against the
+/// whole path relative to the root, with an optional leading `/`.
+struct PatternSet {
+    /// The compiled patterns
+    set: GlobSet,
+    /// Whether each pattern is negated
+    negated: Vec<bool>,
+}
+
+impl PatternSet {
+    /// Compiles the given patterns, returning `None` if there are none
+    fn new(patterns: &[String]) -> Result<Option<Self>, globset::Error> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = GlobSetBuilder::new();
+        let mut negated = Vec::with_capacity(patterns.len());
+        for pattern in patterns {
+            let (pattern, is_negated) = match pattern.strip_prefix('!') {
+                Some(pattern) => (pattern, true),
+                None => (pattern.as_str(), false),
+            };
+            let pattern = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{pattern}"),
+            };
+            builder.add(GlobBuilder::new(&pattern).literal_separator(true).build()?);
+            negated.push(is_negated);
+        }
+
+        Ok(Some(Self {
+            set: builder.build()?,
+            negated,
+        }))
+    }
+
+    /// Returns whether the last pattern that matches the path is a positive one,
+    /// or `None` if no pattern matches
+    fn matches(&self, path: &Path) -> Option<bool> {
+        self.set
+            .matches(path)
+            .into_iter()
+            .max()
+            .map(|i| !self.negated[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    fn patterns(patterns: &[&str]) -> PatternSet {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        PatternSet::new(&patterns).unwrap().unwrap()
+    }
+
+    #[test]
+    fn last_matching_pattern_wins() {
+        let set = patterns(&["*.rs", "!main.rs"]);
+        assert_eq!(set.matches(Path::new("lib.rs")), Some(true));
+        assert_eq!(set.matches(Path::new("src/lib.rs")), Some(true));
+        assert_eq!(set.matches(Path::new("src/main.rs")), Some(false));
+        assert_eq!(set.matches(Path::new("README.md")), None);
+
+        let set = patterns(&["!main.rs", "*.rs"]);
+        assert_eq!(set.matches(Path::new("src/main.rs")), Some(true));
+    }
+
+    #[test]
+    fn patterns_with_a_slash_match_the_relative_path() {
+        let set = patterns(&["src/**"]);
+        assert_eq!(set.matches(Path::new("src/lib.rs")), Some(true));
+        assert_eq!(set.matches(Path::new("src/cli/args.rs")), Some(true));
+        assert_eq!(set.matches(Path::new("tests/src/lib.rs")), None);
+
+        let set = patterns(&["docs/*.md"]);
+        assert_eq!(set.matches(Path::new("docs/guide.md")), Some(true));
+        assert_eq!(set.matches(Path::new("docs/api/index.md")), None);
+        assert_eq!(set.matches(Path::new("guide.md")), None);
+
+        let set = patterns(&["/Cargo.toml"]);
+        assert_eq!(set.matches(Path::new("Cargo.toml")), Some(true));
+        assert_eq!(set.matches(Path::new("sub/Cargo.toml")), None);
+    }
+
+    #[test]
+    fn regexes_match_the_name_or_the_relative_path() {
+        let regexes = |include: &[&str], exclude: &[&str], path, ignore_case| {
+            let strings = |r: &[&str]| Some(r.iter().map(|r| r.to_string()).collect());
+            let cfg = ConfigBuilder {
+                regex_include: strings(include),
+                regex_exclude: strings(exclude),
+                regex_path: path,
+                regex_ignore_case: ignore_case,
+                ..Default::default()
+            }
+            .build();
+            FileFilter::new(&cfg).unwrap()
+        };
+        let included = |filter: &FileFilter, path: &str| {
+            filter.should_include_path(Path::new(path), false, None)
+        };
+
+        let filter = regexes(&[r"\.rs$"], &["^test_"], false, false);
+        assert!(included(&filter, "src/lib.rs"));
+        assert!(!included(&filter, "src/test_lib.rs"));
+        assert!(!included(&filter, "README.md"));
+        // Directories are kept, so that the structure of the tree is preserved
+        assert!(filter.should_include_path(Path::new("test_dir"), true, None));
+
+        // The name is matched by default, so that `^src/` never matches
+        let filter = regexes(&["^src/"], &[], false, false);
+        assert!(!included(&filter, "src/lib.rs"));
+        let filter = regexes(&["^src/"], &[], true, false);
+        assert!(included(&filter, "src/lib.rs"));
+        assert!(!included(&filter, "tests/src.rs"));
+
+        let filter = regexes(&["^readme"], &[], false, false);
+        assert!(!included(&filter, "README.md"));
+        let filter = regexes(&["^readme"], &[], false, true);
+        assert!(included(&filter, "README.md"));
+    }
+
+    #[test]
+    fn invalid_regexes_are_reported_with_the_pattern() {
+        let error = FileFilter::compile_regexes(&["ok".to_string(), "(open".to_string()], false)
+            .err()
+            .unwrap();
+        assert!(error.contains("`(open`"), "{error}");
     }
 }
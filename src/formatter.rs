@@ -1,190 +1,703 @@
-use std::io;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
 
+use crate::columns::Column;
 use crate::config::Config;
+use crate::diff::{Diff, DiffStatus};
+use crate::git::{GitState, GitStatus};
 use crate::helpers;
 use crate::helpers::ansi::{Ansi, AnsiString};
-use crate::tree::{NodeType, TreeNode};
+use crate::stats::Statistics;
+use crate::tree::{NodeType, TreeNode, Visitor};
 
 /// Defines the interface for different output formatters
-pub trait Formatter {
-    /// Formats the given tree node into a string representation
-    fn format(
-        &self,
-        node: &TreeNode,
-        cfg: &Config,
-        stats: &crate::stats::Statistics,
-    ) -> io::Result<String>;
+///
+/// Formatters receive the tree as a stream of `Visitor` events and write their output
+/// as they go, so that the first lines show up before the traversal has finished.
+pub trait Formatter: Visitor {
+    /// Writes whatever comes after the tree, once all the entries have been visited
+    fn finish(&mut self, stats: &Statistics) -> io::Result<()>;
+
+    /// Formats a tree that has already been built
+    fn format(&mut self, node: &TreeNode, stats: &Statistics) -> io::Result<()> {
+        node.walk(self)?;
+        self.finish(stats)
+    }
 }
 
 /// Implements text-based tree formatting
-pub struct TextFormatter;
+pub struct TextFormatter<'a> {
+    /// The configuration that control formatting options
+    cfg: &'a Config,
+    /// The writer to write the output to
+    out: &'a mut dyn Write,
+    /// For each directory currently entered, whether it is the last child of its parent
+    ancestors: Vec<bool>,
+    /// The columns to show before the tree, including the git status when requested
+    columns: Vec<Column>,
+    /// The width of each column, measured over the whole tree when it is formatted at once
+    widths: Vec<usize>,
+    /// The time that relative timestamps are relative to
+    now: SystemTime,
+}
+
+impl<'a> TextFormatter<'a> {
+    /// Creates a new `TextFormatter` that writes to the given writer
+    pub fn new(cfg: &'a Config, out: &'a mut dyn Write) -> Self {
+        let mut columns = cfg.columns.clone();
+        if cfg.git_status && !columns.contains(&Column::Git) {
+            columns.push(Column::Git);
+        }
+        if cfg.changed.is_some() && !columns.contains(&Column::Change) {
+            columns.push(Column::Change);
+        }
+        Self {
+            cfg,
+            out,
+            ancestors: Vec::new(),
+            widths: columns.iter().map(|c| c.min_width(cfg)).collect(),
+            columns,
+            now: SystemTime::now(),
+        }
+    }
+
+    /// Widens the columns to fit the values of a node and all of its descendants
+    fn measure_columns(&mut self, node: &TreeNode) {
+        for (column, width) in self.columns.iter().zip(self.widths.iter_mut()) {
+            let value = column.render(node, self.cfg, self.now);
+            *width = (*width).max(value.chars().count());
+        }
+        for child in &node.children {
+            self.measure_columns(child);
+        }
+    }
 
-impl TextFormatter {
-    /// Recursively formats a tree node and its children
+    /// Renders the configured columns of a node, each padded to the width of the column
     ///
-    /// `prefix`: The indentation string for the current level. (Used in recursive calls)
-    /// `is_last`: True if the node is the last child of its parent, influencing branch characters
-    /// `cfg`: The configuration that control formatting options
-    fn format_node(&self, node: &TreeNode, prefix: &str, is_last: bool, cfg: &Config) -> String {
-        let mut output = String::new();
+    /// The widths are measured up front when a whole tree is formatted (see `format`).
+    /// When the entries are streamed instead, every column starts out wide enough for
+    /// most values and grows as needed, so the earlier lines may not line up.
+    fn format_columns(&mut self, node: &TreeNode) -> String {
+        let mut line = String::new();
+        for (column, width) in self.columns.iter().zip(self.widths.iter_mut()) {
+            let value = column.render(node, self.cfg, self.now);
+            *width = (*width).max(value.chars().count());
+            if *column == Column::Git
+                && let Some(status) = node.git
+                && !self.cfg.no_color
+            {
+                line.push_str(&git_marker(status));
+                line.push(' ');
+            } else if *column == Column::Change
+                && let Some(change) = node.change
+                && !self.cfg.no_color
+            {
+                line.push_str(&change_marker(change));
+                line.push(' ');
+            } else if matches!(column, Column::Diff | Column::Side)
+                && let Some(diff) = &node.diff
+                && !self.cfg.no_color
+            {
+                line.push_str(&value.ansi(&[diff_color(diff.status)]));
+                line.push(' ');
+            } else if column.is_numeric() {
+                line.push_str(&format!("{value:>width$} ", width = *width));
+            } else {
+                line.push_str(&format!("{value:<width$} ", width = *width));
+            }
+        }
+        line
+    }
 
-        // Determine the correct branch character (├── or └──)
-        let branch = if is_last {
-            &cfg.last_prefix
-        } else {
-            &cfg.prefix
-        };
+    /// Writes the line for a single tree node
+    ///
+    /// The root node is written without any prefix. For every other node, the indentation
+    /// is derived from its ancestors (excluding the root), followed by the branch characters.
+    /// `is_last`: True if the node is the last child of its parent, influencing branch characters
+    fn format_node(&mut self, node: &TreeNode, is_last: bool) -> io::Result<()> {
+        let cfg = self.cfg;
+        let mut line = self.format_columns(node);
+        line.push_str(&branch(cfg, &self.ancestors, is_last));
 
         // Determine the display name based on the node type
-        let display_name = self.format_display_name(node, cfg, !cfg.no_color);
-
-        // Construct the current line with prefix, branch, and name
-        let mut line = format!("{prefix}{branch}{display_name}");
+        line.push_str(&display_name(node, cfg, !cfg.no_color));
 
         // Add file size if requested
-        if cfg.size {
-            if let Some(size) = node.size {
-                line.push_str(&format!(
-                    " ({})",
-                    helpers::bytes::format(size, &cfg.size_format)
-                ));
-            }
+        if cfg.size
+            && let Some(size) = node.size
+        {
+            line.push_str(&format!(
+                " ({})",
+                helpers::bytes::format(size, &cfg.size_format)
+            ));
         }
 
-        output.push_str(&line);
-        output.push('\n');
+        // Add the match count of the content search, if counted
+        if let Some(matches) = node.matches {
+            let plural = if matches == 1 { "" } else { "es" };
+            line.push_str(&format!(" ({matches} match{plural})"));
+        }
 
-        // Determine the prefix for children based on whether the current node is the last
-        let child_prefix = if is_last {
-            format!("{prefix}    ")
-        } else {
-            format!("{}{}", prefix, &cfg.child_prefix)
-        };
+        // Add the size delta and the origin of a moved entry, in a merged diff tree
+        if let Some(diff) = &node.diff {
+            line.push_str(&format_diff(diff, cfg));
+        }
 
-        // Recursively format children
-        for (i, child) in node.children.iter().enumerate() {
-            output.push_str(&self.format_node(
-                child,
-                &child_prefix,
-                i == node.children.len() - 1, // Check if this child is the last
-                cfg,
+        // Add the group of a duplicate file, and the space that removing the copies frees
+        if let Some(duplicate) = &node.duplicate {
+            line.push_str(&format!(
+                " (dupe #{}, {} reclaimable)",
+                duplicate.group,
+                helpers::bytes::format(duplicate.reclaimable, &cfg.size_format)
             ));
         }
 
-        output
+        // Add the error, if the entry could not be read
+        if let Some(error) = &node.error {
+            let error = format!("[error: {error}]");
+            line.push(' ');
+            if cfg.no_color {
+                line.push_str(&error);
+            } else {
+                line.push_str(&error.ansi(&[Ansi::Red]));
+            }
+        }
+
+        writeln!(self.out, "{line}")
     }
+}
 
-    /// Returns the display name for a `TreeNode` based on its type
-    fn format_display_name(&self, node: &TreeNode, cfg: &Config, ansi: bool) -> String {
-        let name = if cfg.full_path {
-            node.path.to_string_lossy().to_string()
+/// Returns the indentation and branch characters (├── or └──) before the name of a node
+///
+/// The root node has none. For every other node, the indentation is derived from its
+/// ancestors (excluding the root), i.e. whether each of them is the last child of its parent.
+fn branch(cfg: &Config, ancestors: &[bool], is_last: bool) -> String {
+    let Some((_root, ancestors)) = ancestors.split_first() else {
+        return String::new();
+    };
+    let mut branch = String::new();
+    for &ancestor_is_last in ancestors {
+        if ancestor_is_last {
+            branch.push_str("    ");
         } else {
-            node.name.clone()
-        };
+            branch.push_str(&cfg.child_prefix);
+        }
+    }
+    branch.push_str(if is_last {
+        &cfg.last_prefix
+    } else {
+        &cfg.prefix
+    });
+    branch
+}
+
+/// Returns the display name for a `TreeNode` based on its type
+fn display_name(node: &TreeNode, cfg: &Config, ansi: bool) -> String {
+    let name = if cfg.full_path {
+        node.path.to_string_lossy().to_string()
+    } else {
+        node.name.clone()
+    };
+    style_name(node, name, ansi)
+}
 
-        match node.node_type {
-            NodeType::File => {
-                if ansi {
-                    name.ansi(&[Ansi::BrightWhite])
-                } else {
-                    name
-                }
+/// Styles the name (or path) of a `TreeNode` based on its type
+fn style_name(node: &TreeNode, name: String, ansi: bool) -> String {
+    match node.node_type {
+        NodeType::File | NodeType::Unknown => {
+            if ansi {
+                let color = match &node.duplicate {
+                    Some(duplicate) => duplicate_color(duplicate.group),
+                    None => node.git.map_or(Ansi::BrightWhite, git_color),
+                };
+                name.ansi(&[color])
+            } else {
+                name
             }
-            NodeType::Directory => {
-                if ansi {
-                    format!(" {name} ").ansi(&[Ansi::Bold, Ansi::BgYellow])
-                } else {
-                    format!("{name}/")
-                }
+        }
+        NodeType::Directory => {
+            if ansi {
+                format!(" {name} ").ansi(&[Ansi::Bold, Ansi::BgYellow])
+            } else {
+                format!("{name}/")
             }
-            NodeType::SymbolicLink => {
-                let target = std::fs::read_link(&node.path)
-                    .map(|p| p.to_string_lossy().to_string())
-                    .unwrap_or_else(|_| "<unreadable>".to_string());
-                if ansi {
-                    format!("{name} -> {target}").ansi(&[Ansi::BrightCyan])
-                } else {
-                    format!("{name} -> {target}")
-                }
+        }
+        NodeType::SymbolicLink => {
+            let target = node
+                .target
+                .as_ref()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|| "<unreadable>".to_string());
+            if ansi {
+                format!("{name} -> {target}").ansi(&[Ansi::BrightCyan])
+            } else {
+                format!("{name} -> {target}")
             }
         }
     }
 }
 
-impl Formatter for TextFormatter {
-    /// Formats the entire tree, handling the root node specially (no initial indentation)
-    fn format(
+/// Colours the git status marker of an entry like `git status --short`: the staged change
+/// in green, and the change in the working tree in red
+fn git_marker(status: GitStatus) -> String {
+    match status.worktree {
+        _ if status.is_conflicted() => status.marker().ansi(&[Ansi::Bold, Ansi::Red]),
+        GitState::Untracked => status.marker().ansi(&[Ansi::Red]),
+        GitState::Ignored => status.marker().ansi(&[Ansi::BrightBlack]),
+        _ => format!(
+            "{}{}",
+            status.index.code().to_string().ansi(&[Ansi::Green]),
+            status.worktree.code().to_string().ansi(&[Ansi::Red])
+        ),
+    }
+}
+
+/// Colours the mark of a file changed relative to a git revision
+fn change_marker(change: GitState) -> String {
+    let color = match change {
+        GitState::Added => Ansi::Green,
+        GitState::Deleted => Ansi::Red,
+        GitState::Renamed | GitState::Copied => Ansi::Cyan,
+        _ => Ansi::Yellow,
+    };
+    change.code().to_string().ansi(&[color])
+}
+
+/// Returns the colour of the mark of an entry of a merged diff tree
+fn diff_color(status: DiffStatus) -> Ansi {
+    match status {
+        DiffStatus::Added => Ansi::Green,
+        DiffStatus::Removed => Ansi::Red,
+        DiffStatus::Moved => Ansi::Cyan,
+        _ => Ansi::Yellow,
+    }
+}
+
+/// Describes the size delta of an entry of a merged diff tree, e.g. ` (+120B)`, and
+/// where it was moved from
+fn format_diff(diff: &Diff, cfg: &Config) -> String {
+    let mut details = String::new();
+    if diff.size_delta != 0 {
+        let sign = if diff.size_delta > 0 { '+' } else { '-' };
+        let delta = helpers::bytes::format(diff.size_delta.unsigned_abs(), &cfg.size_format);
+        details.push_str(&format!(" ({sign}{delta})"));
+    }
+    if let Some(from) = &diff.moved_from {
+        details.push_str(&format!(" (from {})", from.display()));
+    }
+    details
+}
+
+/// Returns the colour of the name of a duplicate file, which is the same for the files of
+/// a group, and cycles through a few colours so that neighbouring groups stand apart
+fn duplicate_color(group: usize) -> Ansi {
+    const COLORS: [Ansi; 5] = [
+        Ansi::BrightYellow,
+        Ansi::BrightCyan,
+        Ansi::BrightMagenta,
+        Ansi::BrightGreen,
+        Ansi::BrightBlue,
+    ];
+    COLORS[group.saturating_sub(1) % COLORS.len()]
+}
+
+/// Returns the colour of the name of a file with the given git status
+fn git_color(status: GitStatus) -> Ansi {
+    if status.is_conflicted() {
+        return Ansi::BrightMagenta;
+    }
+    match status.worktree {
+        GitState::Untracked => Ansi::BrightRed,
+        GitState::Ignored => Ansi::BrightBlack,
+        GitState::Unmodified => Ansi::BrightGreen,
+        _ => Ansi::BrightYellow,
+    }
+}
+
+impl Visitor for TextFormatter<'_> {
+    fn enter_dir(&mut self, node: &TreeNode, is_last: bool) -> io::Result<()> {
+        self.format_node(node, is_last)?;
+        self.ancestors.push(is_last);
+        Ok(())
+    }
+
+    fn entry(&mut self, node: &TreeNode, is_last: bool) -> io::Result<()> {
+        self.format_node(node, is_last)
+    }
+
+    fn leave_dir(&mut self, _node: &TreeNode) -> io::Result<()> {
+        self.ancestors.pop();
+        Ok(())
+    }
+}
+
+impl Formatter for TextFormatter<'_> {
+    /// Appends the summary, if requested
+    fn finish(&mut self, stats: &Statistics) -> io::Result<()> {
+        if self.cfg.summary {
+            writeln!(self.out)?;
+            writeln!(self.out, "{stats}")?;
+        }
+        Ok(())
+    }
+
+    /// Formats a tree that has already been built, with the columns as wide as their
+    /// widest values, so that every line lines up
+    fn format(&mut self, node: &TreeNode, stats: &Statistics) -> io::Result<()> {
+        self.measure_columns(node);
+        node.walk(self)?;
+        self.finish(stats)
+    }
+}
+
+/// Implements a two-column rendering of a tree merged by `diff::compare`, with the
+/// entries of the left tree on the left, and those of the right tree on the right
+///
+/// The width of the left column depends on the whole tree, so the output cannot be
+/// streamed, and the configured columns are not shown.
+pub struct SideBySideFormatter<'a> {
+    /// The configuration that control formatting options
+    cfg: &'a Config,
+    /// The writer to write the output to
+    out: &'a mut dyn Write,
+    /// The path of the left root, as the merged tree has the paths of the right tree
+    left_root: &'a Path,
+}
+
+/// A line of the side-by-side rendering
+struct Row {
+    /// The entry on the left, if any
+    left: String,
+    /// The mark of how the two sides differ
+    marker: String,
+    /// The entry on the right, if any
+    right: String,
+}
+
+impl<'a> SideBySideFormatter<'a> {
+    /// Creates a new `SideBySideFormatter` that writes to the given writer
+    pub fn new(cfg: &'a Config, out: &'a mut dyn Write, left_root: &'a Path) -> Self {
+        Self {
+            cfg,
+            out,
+            left_root,
+        }
+    }
+
+    /// Formats a merged tree, followed by the summary if requested
+    pub fn format(&mut self, tree: &TreeNode, stats: &Statistics) -> io::Result<()> {
+        let mut rows = Vec::new();
+        let mut ancestors = (Vec::new(), Vec::new());
+        self.collect_rows(tree, &tree.path, &mut ancestors, (true, true), &mut rows);
+        let width = rows
+            .iter()
+            .map(|row| helpers::ansi::visible_width(&row.left))
+            .max()
+            .unwrap_or_default();
+        for row in rows {
+            let padding = width - helpers::ansi::visible_width(&row.left);
+            let line = format!(
+                "{}{} {} {}",
+                row.left,
+                " ".repeat(padding),
+                row.marker,
+                row.right
+            );
+            writeln!(self.out, "{}", line.trim_end())?;
+        }
+        if self.cfg.summary {
+            writeln!(self.out)?;
+            writeln!(self.out, "{stats}")?;
+        }
+        Ok(())
+    }
+
+    /// Adds the rows of a node and of its descendants, in display order
+    ///
+    /// The branches of each side are drawn from the entries on that side only, so e.g.
+    /// the last entry of a directory on the left may not be its last entry on the right.
+    ///
+    /// `right_root`: The path of the root of the merged tree
+    /// `ancestors`: For each directory entered, whether it is the last child of its
+    /// parent, on the left and on the right
+    /// `is_last`: Whether the node is the last child of its parent, on the left and on
+    /// the right
+    fn collect_rows(
         &self,
         node: &TreeNode,
-        cfg: &Config,
-        stats: &crate::stats::Statistics,
-    ) -> io::Result<String> {
-        let mut output = String::new();
-
-        // Handle the root node without any prefix/indentation
-        let mut line = self.format_display_name(node, cfg, !cfg.no_color);
-
-        // Add file size to root if requested
-        if cfg.size {
-            if let Some(size) = node.size {
-                line.push_str(&format!(
-                    " ({})",
-                    helpers::bytes::format(size, &cfg.size_format)
-                ));
+        right_root: &Path,
+        ancestors: &mut (Vec<bool>, Vec<bool>),
+        is_last: (bool, bool),
+        rows: &mut Vec<Row>,
+    ) {
+        let status = side_status(node);
+        let ansi = !self.cfg.no_color;
+
+        // The size on the left is what the size on the right would be without the change
+        let delta = node.diff.as_ref().map_or(0, |d| d.size_delta);
+        let left_size = match status {
+            DiffStatus::Removed => node.size,
+            _ => node.size.map(|size| size.saturating_add_signed(-delta)),
+        };
+
+        // The merged tree has the names and paths of the right tree
+        let left_path = match node.path.strip_prefix(right_root) {
+            Ok(relative) if relative.as_os_str().is_empty() => self.left_root.to_path_buf(),
+            Ok(relative) => self.left_root.join(relative),
+            Err(_) => node.path.clone(),
+        };
+        let left_name = match left_path.file_name() {
+            Some(name) if !self.cfg.full_path => name.to_string_lossy(),
+            _ => left_path.as_os_str().to_string_lossy(),
+        };
+        let left = if is_on_left(status) {
+            format!(
+                "{}{}",
+                branch(self.cfg, &ancestors.0, is_last.0),
+                self.cell(style_name(node, left_name.to_string(), ansi), left_size)
+            )
+        } else {
+            String::new()
+        };
+        let right = if is_on_right(status) {
+            format!(
+                "{}{}",
+                branch(self.cfg, &ancestors.1, is_last.1),
+                self.cell(display_name(node, self.cfg, ansi), node.size)
+            )
+        } else {
+            String::new()
+        };
+        let marker = status.side_marker().to_string();
+        let marker = if ansi && status != DiffStatus::Unchanged {
+            marker.ansi(&[diff_color(status)])
+        } else {
+            marker
+        };
+        rows.push(Row {
+            left,
+            marker,
+            right,
+        });
+
+        if matches!(node.node_type, NodeType::Directory) {
+            let last_on = |side: fn(DiffStatus) -> bool| {
+                node.children
+                    .iter()
+                    .rposition(|child| side(side_status(child)))
+            };
+            let (last_left, last_right) = (last_on(is_on_left), last_on(is_on_right));
+            ancestors.0.push(is_last.0);
+            ancestors.1.push(is_last.1);
+            for (i, child) in node.children.iter().enumerate() {
+                let is_last = (last_left == Some(i), last_right == Some(i));
+                self.collect_rows(child, right_root, ancestors, is_last, rows);
             }
+            ancestors.0.pop();
+            ancestors.1.pop();
         }
+    }
 
-        output.push_str(&line);
-        output.push('\n');
-
-        // Recursively format children of the root node
-        // The initial prefix for children is an empty string, as they will handle their own indentation
-        for (i, child) in node.children.iter().enumerate() {
-            output.push_str(&self.format_node(
-                child,
-                "", // Children of the root start with no prefix, format_node handles their indentation
-                i == node.children.len() - 1,
-                cfg,
+    /// Renders the display name of an entry on one side, with its size if requested
+    fn cell(&self, mut cell: String, size: Option<u64>) -> String {
+        if self.cfg.size
+            && let Some(size) = size
+        {
+            cell.push_str(&format!(
+                " ({})",
+                helpers::bytes::format(size, &self.cfg.size_format)
             ));
         }
+        cell
+    }
+}
+
+/// Returns how an entry of a merged tree differs between the two sides
+fn side_status(node: &TreeNode) -> DiffStatus {
+    node.diff
+        .as_ref()
+        .map_or(DiffStatus::Unchanged, |diff| diff.status)
+}
+
+/// Whether an entry with the given status is shown on the left of a comparison
+fn is_on_left(status: DiffStatus) -> bool {
+    !matches!(status, DiffStatus::Added | DiffStatus::Moved)
+}
+
+/// Whether an entry with the given status is shown on the right of a comparison
+fn is_on_right(status: DiffStatus) -> bool {
+    status != DiffStatus::Removed
+}
+
+/// Implements JSON formatting
+///
+/// The output is a pretty-printed object with the `root` node and the `stats`.
+/// Every node is written as soon as it is visited, with its `children` written last.
+pub struct JsonFormatter<'a> {
+    /// The writer to write the output to
+    out: &'a mut dyn Write,
+    /// For each directory currently entered, whether a child has been written yet
+    siblings: Vec<bool>,
+}
+
+impl<'a> JsonFormatter<'a> {
+    /// Creates a new `JsonFormatter` that writes to the given writer
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        Self {
+            out,
+            siblings: Vec::new(),
+        }
+    }
 
-        // Append summary if requested
-        if cfg.summary {
-            output.push('\n');
-            output.push_str(&stats.to_string());
+    /// Returns the indentation for the given level of nesting
+    fn indent(level: usize) -> String {
+        "  ".repeat(level)
+    }
+
+    /// Writes a value as pretty-printed JSON, indented to the given level
+    fn write_value(&mut self, value: &impl serde::Serialize, level: usize) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(value).map_err(io::Error::other)?;
+        let newline = format!("\n{}", Self::indent(level));
+        write!(self.out, "{}", json.replace('\n', &newline))
+    }
+
+    /// Writes the fields of a node, up to and including the opening of its `children` array
+    ///
+    /// Returns the nesting level of the node's object.
+    fn open_node(&mut self, node: &TreeNode) -> io::Result<usize> {
+        // The root is the value of the top-level `root` key, the other nodes are array elements
+        let level = 1 + 2 * self.siblings.len();
+        match self.siblings.last_mut() {
+            None => write!(self.out, "{{\n{}\"root\": ", Self::indent(1))?,
+            Some(has_siblings) => {
+                let separator = if *has_siblings { "," } else { "" };
+                write!(self.out, "{separator}\n{}", Self::indent(level))?;
+                *has_siblings = true;
+            }
         }
 
-        Ok(output)
+        // The fields keep the order they are declared in, as `serde_json` preserves the
+        // order of the keys of a map (the `preserve_order` feature)
+        let mut fields = match serde_json::to_value(node).map_err(io::Error::other)? {
+            serde_json::Value::Object(fields) => fields,
+            _ => unreachable!("a TreeNode always serializes to an object"),
+        };
+        fields.remove("children");
+
+        writeln!(self.out, "{{")?;
+        for (key, value) in fields {
+            write!(self.out, "{}", Self::indent(level + 1))?;
+            self.write_value(&key, level + 1)?;
+            write!(self.out, ": ")?;
+            self.write_value(&value, level + 1)?;
+            writeln!(self.out, ",")?;
+        }
+        write!(self.out, "{}\"children\": [", Self::indent(level + 1))?;
+        Ok(level)
     }
 }
 
-pub struct JsonFormatter;
+impl Visitor for JsonFormatter<'_> {
+    fn enter_dir(&mut self, node: &TreeNode, _is_last: bool) -> io::Result<()> {
+        self.open_node(node)?;
+        self.siblings.push(false);
+        Ok(())
+    }
 
-impl Formatter for JsonFormatter {
-    fn format(
-        &self,
-        node: &TreeNode,
-        _cfg: &Config,
-        stats: &crate::stats::Statistics,
-    ) -> io::Result<String> {
-        let output = serde_json::json!({
-            "root": node,
-            "stats": stats,
-        });
-        serde_json::to_string_pretty(&output).map_err(io::Error::other)
+    fn entry(&mut self, node: &TreeNode, _is_last: bool) -> io::Result<()> {
+        let level = self.open_node(node)?;
+        write!(self.out, "]\n{}}}", Self::indent(level))
+    }
+
+    fn leave_dir(&mut self, _node: &TreeNode) -> io::Result<()> {
+        let has_children = self.siblings.pop().unwrap_or_default();
+        let level = 1 + 2 * self.siblings.len();
+        if has_children {
+            write!(self.out, "\n{}", Self::indent(level + 1))?;
+        }
+        write!(self.out, "]\n{}}}", Self::indent(level))
+    }
+}
+
+impl Formatter for JsonFormatter<'_> {
+    /// Closes the root node and appends the statistics
+    fn finish(&mut self, stats: &Statistics) -> io::Result<()> {
+        write!(self.out, ",\n{}\"stats\": ", Self::indent(1))?;
+        self.write_value(stats, 1)?;
+        writeln!(self.out, "\n}}")
     }
 }
 
 /// Returns the appropriate formatter based on the requested output format
-pub fn get_formatter(format: &OutputFormat) -> Box<dyn Formatter> {
-    match format {
-        OutputFormat::Text => Box::new(TextFormatter),
-        OutputFormat::Json => Box::new(JsonFormatter),
+pub fn get_formatter<'a>(cfg: &'a Config, out: &'a mut dyn Write) -> Box<dyn Formatter + 'a> {
+    match cfg.format {
+        OutputFormat::Text => Box::new(TextFormatter::new(cfg, out)),
+        OutputFormat::Json => Box::new(JsonFormatter::new(out)),
+    }
+}
+
+/// The characters that the branches of the tree are drawn with, unless custom prefixes
+/// are configured
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Charset {
+    /// Box-drawing characters, e.g. `├── `
+    Utf8,
+    /// Plain ASCII characters, e.g. `|-- `, like `tree --charset ascii`
+    Ascii,
+}
+
+impl Charset {
+    /// Returns the prefix for each level of the tree
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "├── ",
+            Self::Ascii => "|-- ",
+        }
+    }
+
+    /// Returns the prefix for the last entry of each branch
+    pub fn last_prefix(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "└── ",
+            Self::Ascii => "`-- ",
+        }
+    }
+
+    /// Returns the prefix for the children of a branch
+    pub fn child_prefix(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "│   ",
+            Self::Ascii => "|   ",
+        }
+    }
+}
+
+impl std::str::FromStr for Charset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf8" | "utf-8" | "unicode" => Ok(Self::Utf8),
+            "ascii" => Ok(Self::Ascii),
+            e => Err(format!("Unknown charset: {e} (expected utf8 or ascii)")),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Charset {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<Charset>().map_err(serde::de::Error::custom)
     }
 }
 
 /// Defines the supported output formats for the tree
 #[derive(Clone, Debug, serde::Deserialize)]
+#[non_exhaustive]
 pub enum OutputFormat {
     Text,
     Json,
@@ -200,3 +713,118 @@ impl std::str::FromStr for OutputFormat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use crate::diff::CompareBy;
+
+    /// Runs a formatter, returning what it wrote
+    fn render(formatter: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        formatter(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_keeps_the_declared_order_of_the_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "hello").unwrap();
+        let cfg = ConfigBuilder {
+            root: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        }
+        .build();
+        let (tree, stats) = crate::build(&cfg).unwrap();
+
+        let json = render(|out| JsonFormatter::new(out).format(&tree, &stats));
+        let position = |key: &str| json.find(&format!("\"{key}\"")).unwrap();
+        assert!(position("name") < position("path"));
+        assert!(position("path") < position("node_type"));
+        assert!(position("node_type") < position("size"));
+        assert!(position("size") < position("children"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn columns_are_as_wide_as_their_widest_value_on_every_line() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "").unwrap();
+        std::fs::create_dir(dir.path().join("z")).unwrap();
+        std::fs::write(dir.path().join("z/0"), "").unwrap();
+        // A file with a hundred links, whose count is wider than the first ones
+        for i in 1..100 {
+            std::fs::hard_link(dir.path().join("z/0"), dir.path().join(format!("z/{i}"))).unwrap();
+        }
+        let cfg = ConfigBuilder {
+            root: Some(dir.path().to_path_buf()),
+            columns: Some(vec![Column::Links]),
+            no_color: true,
+            ..Default::default()
+        }
+        .build();
+        assert!(cfg.requires_full_tree());
+        let (tree, stats) = crate::build(&cfg).unwrap();
+
+        let text = render(|out| TextFormatter::new(&cfg, out).format(&tree, &stats));
+        assert!(text.lines().any(|line| line.starts_with("100 ")));
+        for line in text.lines() {
+            let (links, _) = line.split_at(4);
+            assert!(
+                links.ends_with(' ') && links.trim().parse::<u64>().is_ok(),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn side_by_side_draws_the_branches_of_each_side_from_its_own_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("l/a", "a"),
+            ("l/d/x", "x"),
+            ("l/d/y", "y"),
+            ("l/z", "z"),
+            ("r/a", "a"),
+            ("r/b", "b"),
+            ("r/d/w", "w"),
+            ("r/d/y", "y"),
+        ];
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let tree = |side: &str| {
+            let cfg = ConfigBuilder {
+                root: Some(dir.path().join(side)),
+                no_color: true,
+                ..Default::default()
+            }
+            .build();
+            crate::build(&cfg).unwrap().0
+        };
+        let cfg = ConfigBuilder {
+            no_color: true,
+            ..Default::default()
+        }
+        .build();
+        let merged = crate::diff::compare(tree("l"), tree("r"), &[CompareBy::Size], &cfg);
+        let stats = crate::diff::statistics(&merged);
+
+        let left = dir.path().join("l");
+        let text = render(|out| SideBySideFormatter::new(&cfg, out, &left).format(&merged, &stats));
+        let expected = [
+            "l/        ~ r/",
+            "├── a       ├── a",
+            "          > ├── b",
+            "├── d/    ~ └── d/",
+            "          >     ├── w",
+            "│   ├── x <",
+            "│   └── y       └── y",
+            "└── z     <",
+        ];
+        assert_eq!(text.lines().collect::<Vec<_>>(), expected, "{text}");
+    }
+}
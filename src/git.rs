@@ -0,0 +1,869 @@
+//! This module annotates the entries with their git status, like `git status --short`,
+//! lists the files changed relative to a revision, like `git diff --name-status`, and
+//! reads the trees stored in commits, like `git ls-tree`, and finds the last commit that
+//! touched each file, like `git log`.
+//!
+//! The status of the whole repository is read once, by running git, and the entries are
+//! then looked up by their path.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::predicate::FileKind;
+use crate::source::Stat;
+
+/// The state of an entry on one side of `git status` (the index or the working tree)
+///
+/// The states are ordered by how much attention they need, which is how the states of
+/// the entries of a directory are rolled up into the state of the directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum GitState {
+    /// Not changed
+    Unmodified,
+    /// Ignored by a `.gitignore` file or an exclude file
+    Ignored,
+    /// Not tracked
+    Untracked,
+    /// Changed from a file to a symbolic link, or the other way around
+    TypeChanged,
+    /// Added to the index, or since a revision
+    Added,
+    /// Renamed from another path
+    Renamed,
+    /// Copied from another path
+    Copied,
+    /// Deleted
+    Deleted,
+    /// Modified
+    Modified,
+    /// Not merged, because of a conflict
+    Conflicted,
+}
+
+impl GitState {
+    /// Parses one of the two status letters of `git status --porcelain`
+    fn from_code(code: u8) -> Self {
+        match code {
+            b'M' => Self::Modified,
+            b'A' => Self::Added,
+            b'D' => Self::Deleted,
+            b'R' => Self::Renamed,
+            b'C' => Self::Copied,
+            b'T' => Self::TypeChanged,
+            b'U' => Self::Conflicted,
+            b'?' => Self::Untracked,
+            b'!' => Self::Ignored,
+            _ => Self::Unmodified,
+        }
+    }
+
+    /// Returns the status letter of the state, as shown by `git status --short`
+    pub fn code(&self) -> char {
+        match self {
+            Self::Unmodified => ' ',
+            Self::Ignored => '!',
+            Self::Untracked => '?',
+            Self::TypeChanged => 'T',
+            Self::Added => 'A',
+            Self::Renamed => 'R',
+            Self::Copied => 'C',
+            Self::Deleted => 'D',
+            Self::Modified => 'M',
+            Self::Conflicted => 'U',
+        }
+    }
+}
+
+/// The git status of an entry, in the index (staged) and in the working tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitStatus {
+    /// The state of the entry in the index, compared to `HEAD`
+    pub index: GitState,
+    /// The state of the entry in the working tree, compared to the index
+    pub worktree: GitState,
+}
+
+impl GitStatus {
+    /// Parses the two status letters of `git status --porcelain`
+    fn parse(xy: &[u8]) -> Self {
+        // Both sides of an unmerged entry describe the conflict, e.g. `UU` or `AA`
+        if matches!(xy, b"DD" | b"AU" | b"UD" | b"UA" | b"DU" | b"AA" | b"UU") {
+            return Self {
+                index: GitState::Conflicted,
+                worktree: GitState::Conflicted,
+            };
+        }
+        Self {
+            index: GitState::from_code(xy[0]),
+            worktree: GitState::from_code(xy[1]),
+        }
+    }
+
+    /// Returns the two-character marker of the status, as shown by `git status --short`
+    pub fn marker(&self) -> String {
+        [self.index.code(), self.worktree.code()].iter().collect()
+    }
+
+    /// Whether the entry has a conflict
+    pub fn is_conflicted(&self) -> bool {
+        self.index == GitState::Conflicted
+    }
+
+    /// Whether the entry is untracked or ignored, which git shows as a single state on
+    /// both sides (`??` or `!!`)
+    fn is_untracked(&self) -> bool {
+        matches!(self.index, GitState::Untracked | GitState::Ignored) && self.index == self.worktree
+    }
+
+    /// Combines the status of two entries of a directory, keeping the state that needs the
+    /// most attention on each side
+    ///
+    /// An untracked (or ignored) status is kept whole rather than split into sides, so that
+    /// it never shows up next to the state of a tracked entry, as in `?M`. The changes of
+    /// the tracked entries win over it.
+    fn merge(self, other: Self) -> Self {
+        let clean = |status: &Self| {
+            status.index == GitState::Unmodified && status.worktree == GitState::Unmodified
+        };
+        match (self.is_untracked(), other.is_untracked()) {
+            (true, true) if other.worktree > self.worktree => other,
+            (true, true) => self,
+            (true, false) if clean(&other) => self,
+            (true, false) => other,
+            (false, true) if clean(&self) => other,
+            (false, true) => self,
+            (false, false) => Self {
+                index: self.index.max(other.index),
+                worktree: self.worktree.max(other.worktree),
+            },
+        }
+    }
+}
+
+/// The git status of the entries of a repository
+pub struct GitStatuses {
+    /// The path of the root of the tree, relative to the top of the repository
+    prefix: PathBuf,
+    /// The status of the files that are not clean, by path relative to the top
+    files: HashMap<PathBuf, GitStatus>,
+    /// The status of whole directories (i.e. ignored ones), by path relative to the top
+    dirs: HashMap<PathBuf, GitStatus>,
+    /// The status of the directories, rolled up from the status of their files
+    rollup: HashMap<PathBuf, GitStatus>,
+}
+
+impl GitStatuses {
+    /// Reads the status of the repository that contains `root`
+    ///
+    /// Returns `None` if `root` is not in the working tree of a git repository.
+    pub fn load(root: &Path) -> io::Result<Option<Self>> {
+        let Ok(prefix) = git(root, &["rev-parse", "--show-prefix"]) else {
+            return Ok(None);
+        };
+        let prefix = path_from_bytes(prefix.trim_ascii_end());
+
+        let args = [
+            "status",
+            "--porcelain",
+            "-z",
+            "--untracked-files=all",
+            "--ignored=matching",
+            "--",
+            ".",
+        ];
+        let output = git(root, &args)?;
+
+        let mut statuses = Self {
+            prefix,
+            files: HashMap::new(),
+            dirs: HashMap::new(),
+            rollup: HashMap::new(),
+        };
+        let mut fields = output.split(|&b| b == 0);
+        while let Some(field) = fields.next() {
+            if field.len() < 4 {
+                continue;
+            }
+            let status = GitStatus::parse(&field[..2]);
+            // A rename or a copy is followed by the path it was made from
+            if field[..2].iter().any(|&b| b == b'R' || b == b'C') {
+                fields.next();
+            }
+
+            let path = &field[3..];
+            if let Some(dir) = path.strip_suffix(b"/") {
+                statuses.dirs.insert(path_from_bytes(dir), status);
+            } else {
+                statuses.add_file(path_from_bytes(path), status);
+            }
+        }
+        Ok(Some(statuses))
+    }
+
+    /// Records the status of a file, and rolls it up into its parent directories
+    fn add_file(&mut self, path: PathBuf, status: GitStatus) {
+        // Ignored files do not make the directories that contain them stand out
+        if status.worktree != GitState::Ignored {
+            for dir in path.ancestors().skip(1) {
+                self.rollup
+                    .entry(dir.to_path_buf())
+                    .and_modify(|rolled_up| *rolled_up = rolled_up.merge(status))
+                    .or_insert(status);
+            }
+        }
+        self.files.insert(path, status);
+    }
+
+    /// Returns the status of an entry, or `None` if it is clean
+    ///
+    /// `relative`: The path of the entry relative to the root of the tree
+    pub fn get(&self, relative: &Path, is_dir: bool) -> Option<GitStatus> {
+        let path = self.prefix.join(relative);
+        let own = if is_dir {
+            self.dirs.get(&path).or_else(|| self.rollup.get(&path))
+        } else {
+            self.files.get(&path)
+        };
+
+        // The entries of an ignored directory are ignored too
+        own.or_else(|| path.ancestors().skip(1).find_map(|dir| self.dirs.get(dir)))
+            .copied()
+    }
+}
+
+/// The files that differ from a git revision, along with the directories that lead to them
+pub struct ChangedFiles {
+    /// The change of each file, by path relative to the root of the tree
+    files: HashMap<PathBuf, GitState>,
+    /// The names of the entries of each directory that lead to a change
+    dirs: HashMap<PathBuf, HashSet<OsString>>,
+}
+
+impl ChangedFiles {
+    /// Lists the files under `root` that differ from a revision, in the working tree
+    ///
+    /// With `merge_base`, the files are compared with the merge base of the revision and
+    /// `HEAD` instead, e.g. where the current branch forked from `main`. The untracked
+    /// files are listed as added.
+    pub fn load(root: &Path, rev: &str, merge_base: bool) -> io::Result<Self> {
+        let mut args = vec![
+            "diff",
+            "--name-status",
+            "-z",
+            "--relative",
+            "--find-renames",
+        ];
+        if merge_base {
+            args.push("--merge-base");
+        }
+        args.extend([rev, "--", "."]);
+        let output = git(root, &args)?;
+
+        let mut changes = Self {
+            files: HashMap::new(),
+            dirs: HashMap::new(),
+        };
+        let mut fields = output.split(|&b| b == 0);
+        while let Some(status) = fields.next() {
+            let Some(&code) = status.first() else {
+                continue;
+            };
+            // A rename or a copy lists the path it was made from first, e.g. `R100`
+            if code == b'R' || code == b'C' {
+                fields.next();
+            }
+            if let Some(path) = fields.next() {
+                changes.add(path_from_bytes(path), GitState::from_code(code));
+            }
+        }
+
+        let untracked = git(root, &["ls-files", "-z", "--others", "--exclude-standard"])?;
+        for path in untracked.split(|&b| b == 0).filter(|path| !path.is_empty()) {
+            changes.add(path_from_bytes(path), GitState::Added);
+        }
+        Ok(changes)
+    }
+
+    /// Records the change of a file, along with the directories that lead to it
+    fn add(&mut self, path: PathBuf, state: GitState) {
+        let mut child = path.as_path();
+        while let (Some(parent), Some(name)) = (child.parent(), child.file_name()) {
+            self.dirs
+                .entry(parent.to_path_buf())
+                .or_default()
+                .insert(name.to_os_string());
+            child = parent;
+        }
+        self.files.insert(path, state);
+    }
+
+    /// Returns the change of a file, or `None` if it has not changed
+    ///
+    /// `relative`: The path of the file relative to the root of the tree
+    pub fn get(&self, relative: &Path) -> Option<GitState> {
+        self.files.get(relative).copied()
+    }
+
+    /// Whether an entry has changed, or is a directory that leads to a change
+    pub fn contains(&self, relative: &Path) -> bool {
+        self.files.contains_key(relative) || self.dirs.contains_key(relative)
+    }
+
+    /// Whether an entry is a directory that leads to a change
+    pub fn is_dir(&self, relative: &Path) -> bool {
+        self.dirs.contains_key(relative)
+    }
+
+    /// Returns the names of the entries of a directory that have changed or lead to a change
+    pub fn entries(&self, relative: &Path) -> impl Iterator<Item = &OsString> {
+        self.dirs.get(relative).into_iter().flatten()
+    }
+}
+
+/// An entry of a tree stored in a git commit, as listed by `git ls-tree`
+#[derive(Clone, Debug)]
+pub struct GitEntry {
+    /// The mode of the entry, e.g. `0o100644` for a file or `0o040000` for a directory
+    mode: u32,
+    /// The ID of the object, which the contents of a file are read from
+    oid: String,
+    /// The size in bytes (unknown for directories and submodules)
+    size: Option<u64>,
+    /// The target of a symbolic link
+    target: Option<PathBuf>,
+}
+
+impl GitEntry {
+    /// Whether the entry is a directory (or a submodule, whose contents are not in the tree)
+    pub fn is_dir(&self) -> bool {
+        self.kind() == Some(FileKind::Directory)
+    }
+
+    /// Returns the target of a symbolic link
+    pub fn target(&self) -> Option<&Path> {
+        self.target.as_deref()
+    }
+}
+
+impl Stat for GitEntry {
+    fn kind(&self) -> Option<FileKind> {
+        Some(match self.mode & 0o170000 {
+            0o040000 | 0o160000 => FileKind::Directory,
+            0o120000 => FileKind::Symlink,
+            _ => FileKind::File,
+        })
+    }
+
+    fn size(&self) -> u64 {
+        self.size.unwrap_or_default()
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// Git only records whether a file is executable, so the other bits are the usual ones
+    fn mode(&self) -> Option<u32> {
+        Some(match self.kind() {
+            Some(FileKind::File) => self.mode & 0o777,
+            Some(FileKind::Symlink) => 0o777,
+            _ => 0o755,
+        })
+    }
+
+    fn owner(&self) -> (Option<u32>, Option<u32>) {
+        (None, None)
+    }
+}
+
+/// A tree stored in a git commit, read from the object database without checking it out
+pub struct GitTree {
+    /// The repository, which may be bare
+    repo: PathBuf,
+    /// The entries of the tree, by path relative to the root of the tree
+    entries: HashMap<PathBuf, GitEntry>,
+    /// The names of the entries of each directory
+    children: HashMap<PathBuf, Vec<OsString>>,
+    /// The process that reads the objects, started when the first one is needed
+    objects: Mutex<Option<ObjectReader>>,
+}
+
+impl GitTree {
+    /// Reads the tree of a revision of the repository that contains `root`
+    ///
+    /// The revision can be any tree-ish, e.g. `v1.2.0` or `HEAD~2:src`. Within a working
+    /// tree, the tree of a commit is listed from the directory of `root`, like the files on
+    /// disk would be.
+    pub fn load(root: &Path, rev: &str) -> io::Result<Self> {
+        let prefix = git(root, &["rev-parse", "--show-prefix"])?;
+        let prefix = String::from_utf8_lossy(prefix.trim_ascii_end());
+        let tree_ish = if prefix.is_empty() || rev.contains(':') {
+            rev.to_string()
+        } else {
+            format!("{rev}:{prefix}")
+        };
+        let args = ["ls-tree", "--full-tree", "-r", "-t", "-l", "-z", &tree_ish];
+        let output = git(root, &args)?;
+
+        let mut tree = Self {
+            repo: root.to_path_buf(),
+            entries: HashMap::new(),
+            children: HashMap::new(),
+            objects: Mutex::new(None),
+        };
+        // Each entry reads `<mode> <type> <object> <size>\t<path>`, with the size padded
+        for record in output.split(|&b| b == 0) {
+            let Some(tab) = record.iter().position(|&b| b == b'\t') else {
+                continue;
+            };
+            let mut fields = record[..tab]
+                .split(|&b| b == b' ')
+                .filter(|field| !field.is_empty())
+                .map(String::from_utf8_lossy);
+            let (Some(mode), Some(_), Some(oid), Some(size)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+
+            let path = path_from_bytes(&record[tab + 1..]);
+            if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+                tree.children
+                    .entry(parent.to_path_buf())
+                    .or_default()
+                    .push(name.to_os_string());
+            }
+            let entry = GitEntry {
+                mode: u32::from_str_radix(&mode, 8).unwrap_or_default(),
+                oid: oid.into_owned(),
+                size: size.parse().ok(),
+                target: None,
+            };
+            tree.entries.insert(path, entry);
+        }
+
+        tree.read_link_targets()?;
+        Ok(tree)
+    }
+
+    /// Reads the targets of all the symbolic links, which git stores as blobs
+    fn read_link_targets(&mut self) -> io::Result<()> {
+        let objects = self
+            .objects
+            .get_mut()
+            .expect("the object reader is not poisoned");
+        for entry in self.entries.values_mut() {
+            if entry.kind() == Some(FileKind::Symlink) {
+                let reader = match objects {
+                    Some(reader) => reader,
+                    None => objects.insert(ObjectReader::spawn(&self.repo)?),
+                };
+                entry.target = Some(path_from_bytes(&reader.read(&entry.oid)?));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an entry of the tree, or `None` for the root
+    ///
+    /// `relative`: The path of the entry relative to the root of the tree
+    pub fn entry(&self, relative: &Path) -> Option<&GitEntry> {
+        self.entries.get(relative)
+    }
+
+    /// Returns the names of the entries of a directory
+    pub fn entries(&self, relative: &Path) -> impl Iterator<Item = &OsString> {
+        self.children.get(relative).into_iter().flatten()
+    }
+
+    /// Reads the contents of a file
+    ///
+    /// The files are all read through the same git process, one at a time.
+    pub fn read(&self, relative: &Path) -> io::Result<Vec<u8>> {
+        let entry = self
+            .entry(relative)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let mut objects = self
+            .objects
+            .lock()
+            .expect("the object reader is not poisoned");
+        let reader = match &mut *objects {
+            Some(reader) => reader,
+            None => objects.insert(ObjectReader::spawn(&self.repo)?),
+        };
+        let result = reader.read(&entry.oid);
+        if result.is_err() {
+            // The pipes may be out of step, so the next read starts a new process
+            *objects = None;
+        }
+        result
+    }
+}
+
+/// A running `git cat-file --batch`, which is handed object IDs and writes back
+/// their contents, one after the other
+struct ObjectReader {
+    child: Child,
+    /// The input of git, which is closed when the reader is dropped
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ObjectReader {
+    /// Starts git in a repository
+    fn spawn(repo: &Path) -> io::Result<Self> {
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to run git: {e}")))?;
+        Ok(Self {
+            stdin: child.stdin.take(),
+            stdout: BufReader::new(child.stdout.take().expect("stdout is piped")),
+            child,
+        })
+    }
+
+    /// Reads the contents of an object
+    fn read(&mut self, oid: &str) -> io::Result<Vec<u8>> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .expect("stdin is open until the reader is dropped");
+        writeln!(stdin, "{oid}")?;
+        stdin.flush()?;
+
+        // The object reads `<object> <type> <size>\n<contents>\n`, or `<object> missing\n`
+        let mut header = String::new();
+        if self.stdout.read_line(&mut header)? == 0 {
+            return Err(io::Error::other("git cat-file exited early"));
+        }
+        let size = header
+            .trim_end()
+            .rsplit(' ')
+            .next()
+            .and_then(|size| size.parse::<usize>().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("git cat-file: {}", header.trim_end()),
+                )
+            })?;
+        let mut contents = vec![0; size + 1];
+        self.stdout.read_exact(&mut contents)?;
+        contents.pop();
+        Ok(contents)
+    }
+}
+
+impl Drop for ObjectReader {
+    /// Closes the input of git, so that it exits, and waits for it
+    fn drop(&mut self) {
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+/// A commit, as shown by the history columns
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Commit {
+    /// The full hash
+    pub hash: String,
+    /// The abbreviated hash, e.g. `1a2b3c4`
+    pub short_hash: String,
+    /// The name of the author
+    pub author: String,
+    /// The date of the commit
+    #[serde(
+        serialize_with = "crate::helpers::time::serialize_rfc3339",
+        deserialize_with = "crate::helpers::time::deserialize_rfc3339"
+    )]
+    pub date: SystemTime,
+    /// The first line of the message
+    pub subject: String,
+}
+
+impl Commit {
+    /// Parses a commit formatted with `HISTORY_FORMAT`, without the leading separator
+    fn parse(header: &[u8]) -> Option<Self> {
+        let header = String::from_utf8_lossy(header);
+        let mut fields = header.split('\x1f');
+        let (hash, short_hash, author, date, subject) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+        Some(Self {
+            hash: hash.to_string(),
+            short_hash: short_hash.to_string(),
+            author: author.to_string(),
+            date: UNIX_EPOCH + Duration::from_secs(date.parse().ok()?),
+            subject: subject.to_string(),
+        })
+    }
+}
+
+/// The format of the commits listed by `git log`, starting with a record separator
+const HISTORY_FORMAT: &str = "--format=%x1e%H%x1f%h%x1f%an%x1f%ct%x1f%s";
+
+/// The last commit that touched each file of a tree, along with the most recent one of
+/// the files in each directory
+pub struct History {
+    /// The last commit of each file, by path relative to the root of the tree
+    files: HashMap<PathBuf, Arc<Commit>>,
+    /// The most recent commit of the files in each directory
+    dirs: HashMap<PathBuf, Arc<Commit>>,
+}
+
+impl History {
+    /// Finds the last commit that touched each file under `root`, in the history of a
+    /// revision (`HEAD` by default)
+    ///
+    /// The history is walked once, from the newest commit on, and only until every file
+    /// has been found. Returns `None` if `root` is not in a repository with commits.
+    pub fn load(root: &Path, rev: Option<&str>) -> io::Result<Option<Self>> {
+        // The history of a tree-ish such as `v1.2.0:src` is the history of its commit,
+        // limited to its directory, whose paths git shows from the top of the repository
+        let (start, dir) = match rev.and_then(|rev| rev.split_once(':')) {
+            Some((start, path)) => (start, Some(top_relative(root, path)?)),
+            None => (rev.unwrap_or("HEAD"), None),
+        };
+        let listing = match dir {
+            Some(_) => git(
+                root,
+                &[
+                    "ls-tree",
+                    "--full-tree",
+                    "-r",
+                    "-z",
+                    "--name-only",
+                    rev.unwrap_or_default(),
+                ],
+            ),
+            None => git(root, &["ls-tree", "-r", "-z", "--name-only", start]),
+        };
+        let Ok(files) = listing else {
+            return Ok(None);
+        };
+        let mut remaining: HashSet<PathBuf> = files
+            .split(|&b| b == 0)
+            .filter(|path| !path.is_empty())
+            .map(path_from_bytes)
+            .collect();
+
+        let mut command = Command::new("git");
+        command
+            .arg("-C")
+            .arg(root)
+            .args(["log", "-z", "--name-only", HISTORY_FORMAT, start]);
+        match &dir {
+            Some(dir) => command.arg("--").arg(format!(":(top){}", dir.display())),
+            None => command.args(["--relative", "--", "."]),
+        };
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to run git: {e}")))?;
+        let mut reader = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        let mut history = Self {
+            files: HashMap::new(),
+            dirs: HashMap::new(),
+        };
+        // Each commit starts with a record separator, and is followed by the paths that it
+        // touched, all separated by NUL bytes
+        let mut commit = None;
+        let mut field = Vec::new();
+        while !remaining.is_empty() && reader.read_until(0, &mut field)? > 0 {
+            let record = field.strip_suffix(&[0]).unwrap_or(&field);
+            if let Some(header) = record.strip_prefix(&[0x1e]) {
+                commit = Commit::parse(header).map(Arc::new);
+            } else if let Some(commit) = &commit {
+                let path = path_from_bytes(record.strip_prefix(b"\n").unwrap_or(record));
+                let path = match &dir {
+                    Some(dir) => path.strip_prefix(dir).unwrap_or(&path).to_path_buf(),
+                    None => path,
+                };
+                if remaining.remove(&path) {
+                    history.add(path, commit);
+                }
+            }
+            field.clear();
+        }
+
+        // Stop git once every file has been found
+        let _ = child.kill();
+        let _ = child.wait();
+        Ok(Some(history))
+    }
+
+    /// Records the last commit of a file, and rolls it up into its parent directories
+    fn add(&mut self, path: PathBuf, commit: &Arc<Commit>) {
+        for dir in path.ancestors().skip(1) {
+            self.dirs
+                .entry(dir.to_path_buf())
+                .and_modify(|newest| {
+                    if commit.date > newest.date {
+                        *newest = Arc::clone(commit);
+                    }
+                })
+                .or_insert_with(|| Arc::clone(commit));
+        }
+        self.files.insert(path, Arc::clone(commit));
+    }
+
+    /// Returns the last commit of a file, or the most recent one of the files in a directory
+    ///
+    /// `relative`: The path of the entry relative to the root of the tree
+    pub fn get(&self, relative: &Path, is_dir: bool) -> Option<&Commit> {
+        let commits = if is_dir { &self.dirs } else { &self.files };
+        commits.get(relative).map(Arc::as_ref)
+    }
+}
+
+/// Resolves the path of a tree-ish such as `HEAD:src` to a path from the top of the repository
+///
+/// Like in git, a path that starts with `./` or `../` is relative to `root` instead.
+fn top_relative(root: &Path, path: &str) -> io::Result<PathBuf> {
+    let path = Path::new(path);
+    if !matches!(
+        path.components().next(),
+        Some(Component::CurDir | Component::ParentDir)
+    ) {
+        return Ok(path.to_path_buf());
+    }
+    let prefix = git(root, &["rev-parse", "--show-prefix"])?;
+    let mut resolved = path_from_bytes(prefix.trim_ascii_end());
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+    Ok(resolved)
+}
+
+/// Runs git in a directory, returning its output, or an error with git's message if it failed
+fn git(dir: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run git: {e}")))?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "git {}: {}",
+            args[0],
+            message.trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Converts a path printed by git into a `PathBuf`
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// Converts a path printed by git into a `PathBuf`
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_statuses_never_mix_untracked_and_tracked_states() {
+        let merge = |a: &[u8], b: &[u8]| {
+            let merged = GitStatus::parse(a).merge(GitStatus::parse(b));
+            assert_eq!(merged, GitStatus::parse(b).merge(GitStatus::parse(a)));
+            merged.marker()
+        };
+        assert_eq!(merge(b"??", b" M"), " M");
+        assert_eq!(merge(b"??", b"A "), "A ");
+        assert_eq!(merge(b"??", b"!!"), "??");
+        assert_eq!(merge(b"??", b"  "), "??");
+        assert_eq!(merge(b"??", b"UU"), "UU");
+        // Tracked changes keep the state that needs the most attention on each side
+        assert_eq!(merge(b"A ", b" M"), "AM");
+        assert_eq!(merge(b"M ", b"D "), "M ");
+        assert_eq!(merge(b"UU", b"MM"), "UU");
+    }
+
+    /// Creates a repository with a single commit of some files
+    fn repository(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q"]).unwrap();
+        commit(dir.path(), files, "init");
+        dir
+    }
+
+    /// Writes some files and commits them
+    fn commit(repo: &Path, files: &[(&str, &str)], message: &str) {
+        for (path, contents) in files {
+            let path = repo.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        git(repo, &["add", "-A"]).unwrap();
+        let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+        git(repo, &[&identity[..], &["commit", "-qm", message]].concat()).unwrap();
+    }
+
+    #[test]
+    fn reads_the_files_of_a_tree_through_one_process() {
+        let dir = repository(&[("a.txt", "first"), ("b.txt", ""), ("c.txt", "third\n")]);
+        let tree = GitTree::load(dir.path(), "HEAD").unwrap();
+        for (path, contents) in [("c.txt", "third\n"), ("a.txt", "first"), ("b.txt", "")] {
+            assert_eq!(tree.read(Path::new(path)).unwrap(), contents.as_bytes());
+        }
+        assert!(tree.objects.lock().unwrap().is_some());
+        assert!(tree.read(Path::new("missing.txt")).is_err());
+        assert_eq!(tree.read(Path::new("a.txt")).unwrap(), b"first");
+    }
+
+    #[test]
+    fn finds_the_history_of_a_directory_of_a_revision() {
+        let dir = repository(&[("src/a.rs", "a"), ("src/b.rs", "b")]);
+        commit(dir.path(), &[("src/a.rs", "changed")], "change a");
+        commit(dir.path(), &[("README.md", "readme")], "add a readme");
+
+        for rev in ["HEAD:src", "HEAD:./src", "HEAD:src/"] {
+            let history = History::load(dir.path(), Some(rev)).unwrap().unwrap();
+            let subject = |path: &str, is_dir| {
+                history
+                    .get(Path::new(path), is_dir)
+                    .map(|commit| commit.subject.as_str())
+            };
+            assert_eq!(subject("a.rs", false), Some("change a"), "{rev}");
+            assert_eq!(subject("b.rs", false), Some("init"), "{rev}");
+            assert_eq!(subject("", true), Some("change a"), "{rev}");
+            assert_eq!(subject("README.md", false), None, "{rev}");
+        }
+    }
+}
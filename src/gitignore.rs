@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder, gitconfig_excludes_path};
+
+/// The gitignore rules that apply to the entries of a directory
+///
+/// Like git, every directory with a `.gitignore` file adds a level on top of the rules
+/// of its parent. A path is matched against the deepest level first, and the first level
+/// with a matching pattern decides, so that nested files can override (or negate with `!`)
+/// the patterns of their parents. At the bottom are the repository's `.git/info/exclude`
+/// file and the global excludes file (`core.excludesFile`).
+///
+/// All the paths given to `IgnoreRules` must be absolute.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    /// The patterns of this level
+    matcher: Gitignore,
+    /// The rules of the parent directory, if any
+    parent: Option<Arc<IgnoreRules>>,
+    /// Whether this level sits above the root of the walk, so that the directories between
+    /// it and the root were never matched against it
+    above_root: bool,
+}
+
+impl IgnoreRules {
+    /// Creates rules that ignore nothing
+    pub fn empty() -> Arc<Self> {
+        Arc::new(Self {
+            matcher: Gitignore::empty(),
+            parent: None,
+            above_root: false,
+        })
+    }
+
+    /// Returns the rules that apply above `root`, from the git repository that contains it
+    ///
+    /// `parents`: Whether to include the `.gitignore` files of the parent directories, up to
+    /// the root of the repository
+    /// `global`: Whether to include git's global excludes file
+    ///
+    /// Outside of a git repository, there are no rules.
+    pub fn for_root(root: &Path, parents: bool, global: bool) -> Arc<Self> {
+        let mut rules = Self::empty();
+        let Some(repo) = root.ancestors().find(|dir| dir.join(".git").exists()) else {
+            return rules;
+        };
+
+        if global && let Some(excludes) = gitconfig_excludes_path() {
+            rules = rules.push_level(repo, &[excludes], true);
+        }
+        if let Some(git_dir) = git_dir(repo) {
+            let exclude = git_dir.join("info").join("exclude");
+            rules = rules.push_level(repo, &[exclude], true);
+        }
+
+        let parents: Vec<&Path> = root
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| parents && dir.starts_with(repo))
+            .collect();
+        for dir in parents.into_iter().rev() {
+            rules = rules.push_level(dir, &[dir.join(".gitignore")], true);
+        }
+        rules
+    }
+
+    /// Adds a level with the patterns of the given ignore files, relative to `dir`
+    ///
+    /// Files that do not exist are skipped, and so are invalid patterns, as git does.
+    /// If there are no patterns at all, the rules are returned unchanged.
+    pub fn push(self: &Arc<Self>, dir: &Path, files: &[PathBuf]) -> Arc<Self> {
+        self.push_level(dir, files, false)
+    }
+
+    /// Adds a level with the patterns of the given ignore files, relative to `dir`, which
+    /// may sit above the root of the walk
+    fn push_level(self: &Arc<Self>, dir: &Path, files: &[PathBuf], above_root: bool) -> Arc<Self> {
+        let mut builder = GitignoreBuilder::new(dir);
+        for file in files.iter().filter(|file| file.is_file()) {
+            builder.add(file);
+        }
+
+        match builder.build() {
+            Ok(matcher) if !matcher.is_empty() => Arc::new(Self {
+                matcher,
+                parent: Some(Arc::clone(self)),
+                above_root,
+            }),
+            _ => Arc::clone(self),
+        }
+    }
+
+    /// Checks whether the given path is ignored
+    ///
+    /// The levels above the root of the walk also match the path's parent directories, as
+    /// the walk never got to skip them: below an ignored `build/`, everything is ignored.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut rules = Some(self);
+        while let Some(level) = rules {
+            let matched = if level.above_root {
+                level.matcher.matched_path_or_any_parents(path, is_dir)
+            } else {
+                level.matcher.matched(path, is_dir)
+            };
+            match matched {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => rules = level.parent.as_deref(),
+            }
+        }
+        false
+    }
+}
+
+/// Returns the git directory of the repository whose working tree is `repo`
+///
+/// In worktrees and submodules, `.git` is a file with a `gitdir:` line pointing to the
+/// git directory. A worktree shares the `info` directory of the main repository, which
+/// its git directory names in a `commondir` file.
+fn git_dir(repo: &Path) -> Option<PathBuf> {
+    let dot_git = repo.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let contents = std::fs::read_to_string(&dot_git).ok()?;
+    let git_dir = repo.join(contents.lines().next()?.strip_prefix("gitdir:")?.trim());
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => Some(git_dir.join(common.trim())),
+        Err(_) => Some(git_dir),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_above_the_root_ignore_everything_below_a_match() {
+        let repo = tempfile::tempdir().unwrap();
+        let repo = repo.path();
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(repo.join("build/sub")).unwrap();
+        std::fs::write(repo.join(".gitignore"), "build/\n").unwrap();
+
+        let rules = IgnoreRules::for_root(&repo.join("build"), true, false);
+        assert!(rules.is_ignored(&repo.join("build/file"), false));
+        assert!(rules.is_ignored(&repo.join("build/sub"), true));
+
+        let rules = IgnoreRules::for_root(&repo.join("build/sub"), true, false);
+        assert!(rules.is_ignored(&repo.join("build/sub/file"), false));
+
+        // Without the parents, nothing above the root applies
+        let rules = IgnoreRules::for_root(&repo.join("build"), false, false);
+        assert!(!rules.is_ignored(&repo.join("build/file"), false));
+    }
+
+    #[test]
+    fn exclude_file_of_a_worktree_is_found_through_its_git_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main");
+        let git_dir = main.join(".git/worktrees/tree");
+        std::fs::create_dir_all(&git_dir).unwrap();
+        std::fs::create_dir_all(main.join(".git/info")).unwrap();
+        std::fs::write(main.join(".git/info/exclude"), "*.log\n").unwrap();
+        std::fs::write(git_dir.join("commondir"), "../..\n").unwrap();
+
+        let worktree = dir.path().join("tree");
+        std::fs::create_dir(&worktree).unwrap();
+        let line = format!("gitdir: {}\n", git_dir.display());
+        std::fs::write(worktree.join(".git"), line).unwrap();
+
+        let rules = IgnoreRules::for_root(&worktree, false, false);
+        assert!(rules.is_ignored(&worktree.join("debug.log"), false));
+        assert!(!rules.is_ignored(&worktree.join("main.rs"), false));
+    }
+}
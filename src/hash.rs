@@ -0,0 +1,194 @@
+//! This module computes digests of the contents of files, for `--hash`.
+//!
+//! The digest of a directory is computed from the names, types and digests of its entries,
+//! like a Merkle tree, so two directories have the same digest if and only if they
+//! contain the same entries, of the same types, with the same contents (or, for the
+//! entries that could not be read, the same names and types).
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::tree::NodeType;
+
+/// An algorithm to compute the digests of files with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum HashAlgorithm {
+    /// BLAKE3, which is much faster than SHA-256
+    #[default]
+    Blake3,
+    /// SHA-256
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Computes the digest of a file, as a lowercase hexadecimal string
+    pub fn hash_file(&self, path: &Path) -> io::Result<String> {
+        self.hash_reader(File::open(path)?)
+    }
+
+    /// Computes the digest of the contents read from a reader, like `hash_file`
+    pub fn hash_reader(&self, mut reader: impl Read) -> io::Result<String> {
+        let mut hasher = self.hasher();
+        io::copy(&mut reader, &mut hasher)?;
+        Ok(hasher.finalize())
+    }
+
+    /// Computes the digest of some bytes, e.g. the target of a symbolic link
+    pub fn hash_bytes(&self, bytes: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+
+    /// Computes the digest of a directory from the names, types and digests of its entries
+    ///
+    /// The entries are sorted by name first, so the digest does not depend on the order
+    /// they are shown in. The type tells apart e.g. an empty file from an empty directory,
+    /// or a symbolic link from a file that contains its target. An entry without a digest
+    /// (e.g. one that could not be read) only contributes its name and type.
+    pub fn hash_dir(&self, mut entries: Vec<(String, NodeType, Option<String>)>) -> String {
+        entries.sort_unstable_by(|(a, ..), (b, ..)| a.cmp(b));
+        let mut hasher = self.hasher();
+        for (name, node_type, digest) in entries {
+            let tag = match node_type {
+                NodeType::Directory => b'd',
+                NodeType::File => b'f',
+                NodeType::SymbolicLink => b'l',
+                NodeType::Unknown => b'?',
+            };
+            hasher.update(&[tag, b' ']);
+            hasher.update(name.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(digest.unwrap_or_default().as_bytes());
+            hasher.update(b"\n");
+        }
+        hasher.finalize()
+    }
+
+    /// Creates a new incremental hasher
+    fn hasher(&self) -> Hasher {
+        match self {
+            Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+/// The state of a digest being computed, with any algorithm
+enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    /// Adds bytes to the digest
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+            Self::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// Returns the digest, as a lowercase hexadecimal string
+    fn finalize(self) -> String {
+        match self {
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Self::Sha256(hasher) => hex(&hasher.finalize()),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodes bytes as a lowercase hexadecimal string
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blake3" | "b3" => Ok(Self::Blake3),
+            "sha256" | "sha-256" => Ok(Self::Sha256),
+            e => Err(format!(
+                "Unknown hash algorithm: {e} (expected blake3 or sha256)"
+            )),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for HashAlgorithm {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<HashAlgorithm>().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_digests_depend_on_the_types_of_the_entries() {
+        for algorithm in [HashAlgorithm::Blake3, HashAlgorithm::Sha256] {
+            let dir = |node_type, digest: &str| {
+                algorithm.hash_dir(vec![("x".to_string(), node_type, Some(digest.to_string()))])
+            };
+            // An empty directory and an empty file
+            let empty_dir = algorithm.hash_dir(Vec::new());
+            let empty_file = algorithm.hash_bytes(b"");
+            assert_ne!(
+                dir(NodeType::Directory, &empty_dir),
+                dir(NodeType::File, &empty_dir)
+            );
+            assert_ne!(
+                dir(NodeType::Directory, &empty_file),
+                dir(NodeType::File, &empty_file)
+            );
+            // A symbolic link, and a file that contains its target
+            let target = algorithm.hash_bytes(b"a.txt");
+            assert_ne!(
+                dir(NodeType::SymbolicLink, &target),
+                dir(NodeType::File, &target)
+            );
+            assert_eq!(dir(NodeType::File, &target), dir(NodeType::File, &target));
+        }
+    }
+
+    #[test]
+    fn directory_digests_do_not_depend_on_the_order_of_the_entries() {
+        let entry = |name: &str, digest: Option<&str>| {
+            (name.to_string(), NodeType::File, digest.map(str::to_string))
+        };
+        let algorithm = HashAlgorithm::default();
+        assert_eq!(
+            algorithm.hash_dir(vec![entry("a", Some("1")), entry("b", None)]),
+            algorithm.hash_dir(vec![entry("b", None), entry("a", Some("1"))])
+        );
+        assert_ne!(
+            algorithm.hash_dir(vec![entry("a", Some("1")), entry("b", None)]),
+            algorithm.hash_dir(vec![entry("a", None), entry("b", Some("1"))])
+        );
+    }
+}
@@ -102,3 +102,19 @@ impl<T: AsRef<str>> AnsiString for T {
         format!("\u{001b}[{}m{}\u{001b}[0m", codes_str, self.as_ref())
     }
 }
+
+/// Returns the number of characters of a string that show up on screen, i.e. without
+/// the ANSI escape sequences added by `AnsiString::ansi`
+pub fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{001b}' {
+            // Skip the rest of the sequence, up to and including the final `m`
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
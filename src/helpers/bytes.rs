@@ -36,6 +36,19 @@ impl Format {
         .to_string()
     }
 
+    /// Returns the number of bytes in one unit of this format
+    fn bytes(&self) -> u64 {
+        match self {
+            Format::Bytes => 1,
+            Format::KiloBytes => 1024,
+            Format::MegaBytes => 1024_u64.pow(2),
+            Format::GigaBytes => 1024_u64.pow(3),
+            Format::TeraBytes => 1024_u64.pow(4),
+            Format::PetaBytes => 1024_u64.pow(5),
+            Format::ExaBytes => 1024_u64.pow(6),
+        }
+    }
+
     fn convert(&self, bytes: u64) -> f64 {
         match self {
             Format::Bytes => bytes as f64,
@@ -56,6 +69,29 @@ pub fn format(bytes: u64, mode: &Format) -> String {
     }
 }
 
+/// Parses a size with an optional unit, e.g. `512`, `10k`, `1.5MB` or `2 GiB`
+///
+/// The units are those of `Format`, and are all powers of 1024.
+pub fn parse(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size: {s} (expected a number with an optional unit)"))?;
+
+    // Accept the binary spellings too, e.g. `KiB`
+    let unit = unit.trim();
+    let format = match unit.to_lowercase().as_str() {
+        "" => Format::Bytes,
+        binary if binary.len() == 3 && binary.ends_with("ib") => binary[..1].parse()?,
+        unit => unit.parse()?,
+    };
+    Ok((number * format.bytes() as f64).round() as u64)
+}
+
 impl std::str::FromStr for Format {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -81,3 +117,38 @@ impl<'de> serde::Deserialize<'de> for Format {
         s.parse::<Format>().map_err(serde::de::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_units() {
+        let cases = [
+            ("0", 0),
+            ("512", 512),
+            (" 7 ", 7),
+            ("10b", 10),
+            ("10 bytes", 10),
+            ("10k", 10 * 1024),
+            ("10K", 10 * 1024),
+            ("10KB", 10 * 1024),
+            ("10 KiB", 10 * 1024),
+            ("1.5MB", 1024 * 1024 * 3 / 2),
+            ("1.5m", 1024 * 1024 * 3 / 2),
+            ("2GiB", 2 * 1024 * 1024 * 1024),
+            ("1t", 1024_u64.pow(4)),
+            ("0.5k", 512),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        for input in ["", "KB", "-1", "1.2.3", "10 parsecs", "10 KiBi", "ten"] {
+            assert!(parse(input).is_err(), "{input}");
+        }
+    }
+}
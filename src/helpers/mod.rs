@@ -1,2 +1,3 @@
 pub mod ansi;
 pub mod bytes;
+pub mod time;
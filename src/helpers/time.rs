@@ -0,0 +1,329 @@
+//! This module provides utilities for working with timestamps.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+
+/// Formats a timestamp as an RFC 3339 string in UTC, e.g. `2025-01-31T12:00:00Z`
+pub fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Serializes a timestamp as an RFC 3339 string
+///
+/// Intended for use with `#[serde(serialize_with = "...")]`.
+pub fn serialize_rfc3339<S: serde::Serializer>(time: &SystemTime, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&rfc3339(*time))
+}
+
+/// Parses an RFC 3339 timestamp, e.g. `2025-01-31T12:00:00Z`
+pub fn parse_rfc3339(s: &str) -> Result<SystemTime, String> {
+    DateTime::parse_from_rfc3339(s)
+        .map(SystemTime::from)
+        .map_err(|e| format!("Invalid timestamp: {s} ({e})"))
+}
+
+/// Deserializes a timestamp from an RFC 3339 string
+///
+/// Intended for use with `#[serde(deserialize_with = "...")]`.
+pub fn deserialize_rfc3339<'de, D: serde::Deserializer<'de>>(d: D) -> Result<SystemTime, D::Error> {
+    let s = <String as serde::Deserialize>::deserialize(d)?;
+    parse_rfc3339(&s).map_err(serde::de::Error::custom)
+}
+
+/// (De)serializes an optional timestamp as an RFC 3339 string
+///
+/// Intended for use with `#[serde(with = "...")]`.
+pub mod optional_rfc3339 {
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Serializes an optional timestamp as an RFC 3339 string, or `null`
+    pub fn serialize<S: Serializer>(time: &Option<SystemTime>, s: S) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => s.serialize_str(&super::rfc3339(*time)),
+            None => s.serialize_none(),
+        }
+    }
+
+    /// Deserializes an optional timestamp from an RFC 3339 string, or `null`
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<SystemTime>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|s| super::parse_rfc3339(&s).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Describes how timestamps are shown in the text output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimeStyle {
+    /// The local date and time, e.g. `2025-01-31 12:00`
+    Iso,
+    /// The time relative to now, e.g. `3 days ago`
+    Relative,
+    /// A custom `strftime` format, e.g. `%d %b %Y`
+    Custom(String),
+}
+
+/// Formats a timestamp in the given style, relative to `now` if needed
+pub fn format(time: SystemTime, style: &TimeStyle, now: SystemTime) -> String {
+    match style {
+        TimeStyle::Iso => DateTime::<Local>::from(time)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        TimeStyle::Relative => relative(time, now),
+        TimeStyle::Custom(fmt) => DateTime::<Local>::from(time).format(fmt).to_string(),
+    }
+}
+
+/// Describes how long ago a timestamp was, e.g. `3 days ago`
+fn relative(time: SystemTime, now: SystemTime) -> String {
+    let Ok(elapsed) = now.duration_since(time) else {
+        return "in the future".to_string();
+    };
+
+    let seconds = elapsed.as_secs();
+    let (count, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3_600 => (seconds / 60, "minute"),
+        3_600..86_400 => (seconds / 3_600, "hour"),
+        86_400..2_592_000 => (seconds / 86_400, "day"),
+        2_592_000..31_536_000 => (seconds / 2_592_000, "month"),
+        _ => (seconds / 31_536_000, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural} ago")
+}
+
+/// A point in time, as given to the `--newer` and `--older` filters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PointInTime {
+    /// A duration before now, e.g. `2d` or `1h30m`
+    Ago(Duration),
+    /// A local date, with an optional time, e.g. `2025-01-31` or `2025-01-31 12:00`
+    Date(SystemTime),
+    /// The modification time of a reference file
+    File(PathBuf),
+}
+
+impl PointInTime {
+    /// Returns the point in time, relative to `now` if needed
+    pub fn resolve(&self, now: SystemTime) -> std::io::Result<SystemTime> {
+        match self {
+            Self::Ago(duration) => Ok(now.checked_sub(*duration).unwrap_or(SystemTime::UNIX_EPOCH)),
+            Self::Date(time) => Ok(*time),
+            Self::File(path) => std::fs::metadata(path)?.modified(),
+        }
+    }
+}
+
+/// Parses a duration made of numbers with units, e.g. `90s`, `2d` or `1h30m`
+///
+/// The units are `s`, `m` (minutes), `h`, `d`, `w` and `y` (365 days).
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration: {s} (expected e.g. 30m, 2d or 1h30m)");
+    let mut seconds = 0_u64;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (number, tail) = rest.split_at(split);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let unit = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit);
+        let multiplier = match unit {
+            "s" | "sec" | "secs" => 1,
+            "m" | "min" | "mins" => 60,
+            "h" | "hour" | "hours" => 3_600,
+            "d" | "day" | "days" => 86_400,
+            "w" | "week" | "weeks" => 604_800,
+            "y" | "year" | "years" => 31_536_000,
+            _ => return Err(invalid()),
+        };
+        seconds = seconds.saturating_add(number.saturating_mul(multiplier));
+        rest = tail;
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Parses a local date with an optional time, or an RFC 3339 timestamp
+fn parse_date(s: &str) -> Option<SystemTime> {
+    if let Ok(time) = parse_rfc3339(s) {
+        return Some(time);
+    }
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(SystemTime::from)
+}
+
+impl std::str::FromStr for PointInTime {
+    type Err = String;
+    /// Parses a duration or a date, falling back to the path of a reference file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(duration) = parse_duration(s) {
+            return Ok(Self::Ago(duration));
+        }
+        if let Some(time) = parse_date(s) {
+            return Ok(Self::Date(time));
+        }
+        let path = PathBuf::from(s);
+        if !path.exists() {
+            return Err(format!(
+                "Invalid point in time: {s} (expected a duration, a date or an existing file)"
+            ));
+        }
+        Ok(Self::File(path))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PointInTime {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<PointInTime>().map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::str::FromStr for TimeStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(fmt) = s.strip_prefix('+') {
+            // Reject invalid formats up front, as they would only fail when rendering
+            if StrftimeItems::new(fmt).any(|item| matches!(item, Item::Error)) {
+                return Err(format!("Invalid time format: {fmt}"));
+            }
+            return Ok(Self::Custom(fmt.to_string()));
+        }
+        match s.to_lowercase().as_str() {
+            "iso" => Ok(Self::Iso),
+            "relative" => Ok(Self::Relative),
+            e => Err(format!(
+                "Unknown time style: {e} (expected iso, relative or +FORMAT)"
+            )),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TimeStyle {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<TimeStyle>().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("0s", 0),
+            ("90s", 90),
+            ("30m", 30 * 60),
+            ("2d", 2 * 86_400),
+            ("1h30m", 3_600 + 30 * 60),
+            ("1w2d", 9 * 86_400),
+            ("1y", 365 * 86_400),
+            ("3days", 3 * 86_400),
+            ("10mins", 600),
+            (" 2h ", 7_200),
+        ];
+        for (input, seconds) in cases {
+            assert_eq!(
+                parse_duration(input),
+                Ok(Duration::from_secs(seconds)),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for input in ["", "h", "10", "5x", "1.5h", "-2d", "2 d", "1h30"] {
+            assert!(parse_duration(input).is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn parses_points_in_time() {
+        let local = |y, mo, d, h, mi| {
+            SystemTime::from(
+                Local
+                    .with_ymd_and_hms(y, mo, d, h, mi, 0)
+                    .earliest()
+                    .unwrap(),
+            )
+        };
+        let cases = [
+            ("2d", PointInTime::Ago(Duration::from_secs(2 * 86_400))),
+            ("2025-01-31", PointInTime::Date(local(2025, 1, 31, 0, 0))),
+            (
+                "2025-01-31 12:30",
+                PointInTime::Date(local(2025, 1, 31, 12, 30)),
+            ),
+            (
+                "2025-01-31T12:30",
+                PointInTime::Date(local(2025, 1, 31, 12, 30)),
+            ),
+            (
+                "2025-01-31T12:00:00Z",
+                PointInTime::Date(SystemTime::UNIX_EPOCH + Duration::from_secs(1_738_324_800)),
+            ),
+            ("Cargo.toml", PointInTime::File(PathBuf::from("Cargo.toml"))),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(input.parse::<PointInTime>(), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_points_in_time() {
+        for input in [
+            "",
+            "yesterday",
+            "2025-13-01",
+            "2025-01-31 25:00",
+            "no/such/file",
+        ] {
+            assert!(input.parse::<PointInTime>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn parses_time_styles() {
+        assert_eq!("iso".parse(), Ok(TimeStyle::Iso));
+        assert_eq!("Relative".parse(), Ok(TimeStyle::Relative));
+        assert_eq!("+%Y".parse(), Ok(TimeStyle::Custom("%Y".to_string())));
+        assert!("+%Q".parse::<TimeStyle>().is_err());
+        assert!("long".parse::<TimeStyle>().is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! List the contents of a directory in a tree format
+//!
+//! This crate powers the `fstree` command-line utility, and exposes the same building
+//! blocks for use in other tools: a `Config` describing what to show, a `TreeBuilder`
+//! that walks the file system into a `TreeNode` (collecting `Statistics` on the way),
+//! and `Formatter`s that render a tree as text or JSON to any `std::io::Write`.
+//!
+//! ```no_run
+//! use fstree::{ConfigBuilder, Formatter, TextFormatter};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // Describe the traversal in code; unset fields fall back to their defaults
+//! let mut builder = ConfigBuilder::default();
+//! builder.root = Some("src".into());
+//! builder.size = true;
+//! builder.max_depth = Some(2);
+//! let cfg = builder.build();
+//!
+//! // Walk the file system into a tree
+//! let (tree, stats) = fstree::build(&cfg)?;
+//!
+//! // Render the tree with any formatter
+//! let mut out = std::io::stdout();
+//! TextFormatter::new(&cfg, &mut out).format(&tree, &stats)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For very large trees, `TreeBuilder::walk` streams the entries into a `Visitor`
+//! (every `Formatter` is one) instead of holding the whole tree in memory.
+
+pub mod columns;
+pub mod config;
+pub mod diff;
+pub mod dupes;
+mod expr;
+mod filter;
+pub mod formatter;
+mod git;
+mod gitignore;
+pub mod hash;
+mod helpers;
+mod metadata;
+mod predicate;
+pub mod scaffold;
+mod search;
+pub mod snapshot;
+mod sort;
+mod source;
+mod stats;
+pub mod tree;
+
+pub use columns::Column;
+pub use config::{Config, ConfigBuilder};
+pub use formatter::{
+    Charset, Formatter, JsonFormatter, OutputFormat, SideBySideFormatter, TextFormatter,
+};
+pub use git::{Commit, GitState, GitStatus};
+pub use hash::HashAlgorithm;
+pub use helpers::bytes::{Format as SizeFormat, parse as parse_size};
+pub use helpers::time::{PointInTime, TimeStyle};
+pub use metadata::Metadata;
+pub use predicate::{FileKind, PermMatch};
+pub use sort::{GroupDirs, SortKey};
+pub use stats::Statistics;
+pub use tree::{NodeType, TreeBuilder, TreeNode, Visitor};
+
+/// Builds the tree rooted at `cfg.root`
+///
+/// Returns the tree along with the statistics collected while walking it.
+pub fn build(cfg: &Config) -> Result<(TreeNode, Statistics), Box<dyn std::error::Error>> {
+    let builder = TreeBuilder::new(cfg)?;
+    let tree = builder.build(&cfg.root)?;
+    Ok((tree, builder.into_stats()))
+}
@@ -3,15 +3,20 @@
 //! This program walks through directories and displays their contents in a
 //! hierarchical tree structure, similar to the Unix tree command.
 
-use crate::config::ConfigBuilder;
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use fstree::columns::Column;
+use fstree::config::{self, Config, ConfigBuilder};
+use fstree::diff::CompareBy;
+use fstree::dupes::Duplicates;
+use fstree::formatter::{OutputFormat, SideBySideFormatter};
+use fstree::scaffold::{Action, EntryKind, Layout};
+use fstree::snapshot::Snapshot;
+use fstree::{diff, formatter, tree};
 
 mod cli;
-mod config;
-mod filter;
-mod formatter;
-mod helpers;
-mod stats;
-mod tree;
 
 /// The main entrypoint of the application
 fn main() {
@@ -21,11 +26,46 @@ fn main() {
     // Load settings from the configuration file, if available
     let config_file = config::load_file();
 
-    // Merge configurations, with command-line arguments taking precedence
-    let cfg = setup_configuration(args, config_file);
-
-    // Execute the main application logic
-    if let Err(e) = run(&cfg) {
+    // Merge configurations, with command-line arguments taking precedence, and
+    // execute the main application logic
+    let result = match args.command {
+        None => run(&setup_configuration(args.root, args.options, config_file)),
+        Some(cli::Command::Snapshot {
+            output,
+            root,
+            options,
+        }) => snapshot(setup_configuration(root, options, config_file), &output),
+        Some(cli::Command::Diff { old, new, options }) => {
+            compare_snapshots(setup_configuration(None, options, config_file), &old, &new)
+        }
+        Some(cli::Command::Compare {
+            left,
+            right,
+            by,
+            side_by_side,
+            options,
+        }) => {
+            let cfg = setup_configuration(None, options, config_file);
+            compare_dirs(cfg, &left, &right, &by, side_by_side)
+        }
+        Some(cli::Command::Dupes { root, options }) => {
+            dupes(setup_configuration(root, options, config_file))
+        }
+        Some(cli::Command::Scaffold {
+            layout,
+            into,
+            dry_run,
+            force,
+            options,
+        }) => {
+            // The prefixes of the configuration file may have been used to draw the diagram
+            let cfg = ConfigBuilder::from(options)
+                .merge(config_file.into())
+                .build();
+            scaffold(&cfg, &layout, &into, dry_run, force)
+        }
+    };
+    if let Err(e) = result {
         eprintln!("Error: {e}");
         std::process::exit(1);
     }
@@ -35,34 +75,239 @@ fn main() {
 /// with a configuration file.
 ///
 /// If the `--no-config` flag is present, only command-line arguments are used.
-fn setup_configuration(args: cli::Args, config_file: config::FileConfig) -> config::Config {
-    if args.no_config {
+fn setup_configuration(
+    root: Option<PathBuf>,
+    options: cli::Options,
+    config_file: config::FileConfig,
+) -> Config {
+    let no_config = options.no_config;
+    let mut args = ConfigBuilder::from(options);
+    args.root = root;
+    if no_config {
         // If `no_config` is set, use only the command-line arguments.
-        ConfigBuilder::from(args).build()
+        args.build()
     } else {
         // Otherwise, merge the configurations together.
-        ConfigBuilder::from(args).merge(config_file.into()).build()
+        args.merge(config_file.into()).build()
     }
 }
 
-/// Executes the main logic of the application.
-fn run(cfg: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
-    // Ensure the root path exists before proceeding
-    if !cfg.root.exists() {
+/// Ensures that a path exists before walking it
+fn check_exists(path: &Path) -> Result<(), Box<dyn Error>> {
+    if !path.exists() {
         return Err(Box::new(std::io::Error::new(
             std::io::ErrorKind::NotFound,
-            format!("path does not exist: {}", &cfg.root.display()),
+            format!("path does not exist: {}", path.display()),
         )));
     }
+    Ok(())
+}
 
-    // Build the directory tree
-    let mut builder = tree::TreeBuilder::new(cfg)?;
-    let tree = builder.build(&cfg.root)?;
+/// Executes the main logic of the application.
+fn run(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    // Ensure the root path exists before proceeding
+    check_exists(&cfg.root)?;
+
+    // Walk the directory tree, streaming the formatted entries to the standard output,
+    // unless the output depends on the whole tree
+    let builder = tree::TreeBuilder::new(cfg)?;
+    let mut stdout = std::io::stdout().lock();
+    let mut formatter = formatter::get_formatter(cfg, &mut stdout);
+    let result = if cfg.requires_full_tree() {
+        builder
+            .build(&cfg.root)
+            .and_then(|tree| formatter.format(&tree, builder.get_stats()))
+    } else {
+        builder
+            .walk(&cfg.root, formatter.as_mut())
+            .and_then(|_| formatter.finish(builder.get_stats()))
+    };
+    ignore_broken_pipe(result)
+}
 
-    // Format and print the tree to the standard output
-    let formatter = formatter::get_formatter(&cfg.format);
-    let output = formatter.format(&tree, cfg, builder.get_stats())?;
-    println!("{output}");
+/// Saves a snapshot of the tree, with the modification times needed to compare it later on
+fn snapshot(mut cfg: Config, output: &Path) -> Result<(), Box<dyn Error>> {
+    check_exists(&cfg.root)?;
+    cfg.metadata = true;
+    Ok(Snapshot::take(&cfg)?.save(output)?)
+}
+
+/// Shows the differences between two snapshots, or between a snapshot and a directory
+///
+/// A directory is walked with the configured filters, and with the hash algorithm of
+/// the snapshot it is compared with, so that the files are compared on the same terms.
+fn compare_snapshots(mut cfg: Config, old: &Path, new: &Path) -> Result<(), Box<dyn Error>> {
+    check_exists(old)?;
+    check_exists(new)?;
+    let load = |path: &Path| (!path.is_dir()).then(|| Snapshot::load(path)).transpose();
+    let (old_snapshot, new_snapshot) = (load(old)?, load(new)?);
+
+    let algorithms: Vec<_> = [&old_snapshot, &new_snapshot]
+        .into_iter()
+        .flatten()
+        .filter_map(|snapshot| snapshot.hash)
+        .collect();
+    if let [a, b] = algorithms[..]
+        && a != b
+    {
+        return Err("the snapshots were taken with different --hash algorithms".into());
+    }
+    if let Some(&algorithm) = algorithms.first() {
+        cfg.hash = Some(algorithm);
+    }
+    cfg.metadata = true;
+
+    let mut walk = |snapshot: Option<Snapshot>, path: &Path| match snapshot {
+        Some(snapshot) => Ok::<_, Box<dyn Error>>(snapshot.root),
+        None => {
+            cfg.root = path.to_path_buf();
+            Ok(fstree::build(&cfg)?.0)
+        }
+    };
+    let old_tree = walk(old_snapshot, old)?;
+    let new_tree = walk(new_snapshot, new)?;
+
+    let tree = diff::diff(old_tree, new_tree, &cfg);
+    if !cfg.columns.contains(&Column::Diff) {
+        cfg.columns.insert(0, Column::Diff);
+    }
+    let mut stdout = std::io::stdout().lock();
+    let mut formatter = formatter::get_formatter(&cfg, &mut stdout);
+    ignore_broken_pipe(formatter.format(&tree, &diff::statistics(&tree)))
+}
 
+/// Compares two directories entry by entry, and shows the merged tree, either with a
+/// column marking the side of each difference, or as two trees side by side
+fn compare_dirs(
+    mut cfg: Config,
+    left: &Path,
+    right: &Path,
+    by: &[CompareBy],
+    side_by_side: bool,
+) -> Result<(), Box<dyn Error>> {
+    check_exists(left)?;
+    check_exists(right)?;
+    if by.contains(&CompareBy::Hash) {
+        cfg.hash = Some(cfg.hash.unwrap_or_default());
+    }
+    if by.contains(&CompareBy::Mtime) {
+        cfg.metadata = true;
+    }
+
+    // Both sides are walked with the same configuration, and so the same filters
+    cfg.root = left.to_path_buf();
+    let (left_tree, _) = fstree::build(&cfg)?;
+    cfg.root = right.to_path_buf();
+    let (right_tree, _) = fstree::build(&cfg)?;
+
+    let tree = diff::compare(left_tree, right_tree, by, &cfg);
+    let stats = diff::statistics(&tree);
+    let mut stdout = std::io::stdout().lock();
+    let result = if side_by_side && matches!(cfg.format, OutputFormat::Text) {
+        SideBySideFormatter::new(&cfg, &mut stdout, left).format(&tree, &stats)
+    } else {
+        if !cfg.columns.contains(&Column::Side) {
+            cfg.columns.insert(0, Column::Side);
+        }
+        formatter::get_formatter(&cfg, &mut stdout).format(&tree, &stats)
+    };
+    ignore_broken_pipe(result)
+}
+
+/// Finds the files with identical contents, and shows them in a tree marked with their
+/// groups, or lists the groups as JSON
+fn dupes(mut cfg: Config) -> Result<(), Box<dyn Error>> {
+    check_exists(&cfg.root)?;
+    if cfg.rev.is_some() {
+        return Err("dupes cannot be used with --rev".into());
+    }
+    // Only the files that may have duplicates are hashed, instead of every file, so the
+    // digest column only shows the digests of the duplicates
+    let algorithm = cfg.hash.take().unwrap_or_default();
+    let digest_column = cfg.columns.iter().position(|c| *c == Column::Digest);
+    if let Some(i) = digest_column {
+        cfg.columns.remove(i);
+    }
+    // The files are compared by their actual size, not the space they use on disk
+    cfg.disk_usage = false;
+
+    let (tree, stats) = fstree::build(&cfg)?;
+    if let Some(i) = digest_column {
+        cfg.columns.insert(i, Column::Digest);
+    }
+    let duplicates = Duplicates::find(&tree, algorithm, &cfg)?;
+    let mut stdout = std::io::stdout().lock();
+    let result = if matches!(cfg.format, OutputFormat::Json) {
+        serde_json::to_writer_pretty(&mut stdout, &duplicates)
+            .map_err(std::io::Error::other)
+            .and_then(|_| writeln!(stdout))
+    } else {
+        let result = match duplicates.annotate(tree) {
+            Some(tree) => formatter::get_formatter(&cfg, &mut stdout).format(&tree, &stats),
+            None => Ok(()),
+        };
+        result.and_then(|_| writeln!(stdout, "{}", duplicates.summary(&cfg)))
+    };
+    ignore_broken_pipe(result)
+}
+
+/// Creates the entries of a tree diagram or snapshot in a directory, or only shows what
+/// would be created
+///
+/// Nothing is created if any existing file is in the way, unless it can be overwritten.
+fn scaffold(
+    cfg: &Config,
+    layout: &Path,
+    into: &Path,
+    dry_run: bool,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let steps = Layout::load(layout, cfg)?.plan(into, force)?;
+    let mut stdout = std::io::stdout().lock();
+    let (mut dirs, mut files, mut links) = (0, 0, 0);
+    for step in &steps {
+        if dry_run {
+            let slash = if step.kind == EntryKind::Directory {
+                "/"
+            } else {
+                ""
+            };
+            writeln!(
+                stdout,
+                "{:<9} {}{slash}",
+                step.action.verb(),
+                step.path.display()
+            )?;
+        } else {
+            step.apply()?;
+        }
+        match (step.action, &step.kind) {
+            (Action::Keep, _) => {}
+            (_, EntryKind::Directory) => dirs += 1,
+            (_, EntryKind::SymbolicLink(_)) => links += 1,
+            _ => files += 1,
+        }
+    }
+
+    let verb = if dry_run { "Would create" } else { "Created" };
+    let count =
+        |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+    writeln!(
+        stdout,
+        "{verb} {}, {} and {} in {}",
+        count(dirs, "directory", "directories"),
+        count(files, "file", "files"),
+        count(links, "symbolic link", "symbolic links"),
+        into.display()
+    )?;
     Ok(())
 }
+
+/// Treats a closed pipe (e.g. `fstree | head`) as success, as it just means nobody wants
+/// the rest of the output
+fn ignore_broken_pipe(result: std::io::Result<()>) -> Result<(), Box<dyn Error>> {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
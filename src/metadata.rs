@@ -0,0 +1,121 @@
+//! This module collects the additional file system metadata of tree nodes.
+//!
+//! Collecting it costs a few extra lookups per entry (e.g. resolving user and group
+//! names), so it is only done when requested in the configuration.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::time::optional_rfc3339;
+
+/// The file system metadata of a `TreeNode`
+///
+/// Every field is optional, as not every platform (or file system) provides it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Metadata {
+    /// The permission bits, including the setuid, setgid and sticky bits (e.g. `0o755`)
+    pub mode: Option<u32>,
+    /// The user ID of the owner
+    pub uid: Option<u32>,
+    /// The group ID of the owner
+    pub gid: Option<u32>,
+    /// The name of the owning user
+    pub user: Option<String>,
+    /// The name of the owning group
+    pub group: Option<String>,
+    /// The time of the last modification of the contents
+    #[serde(with = "optional_rfc3339")]
+    pub modified: Option<SystemTime>,
+    /// The time of the last access
+    #[serde(with = "optional_rfc3339")]
+    pub accessed: Option<SystemTime>,
+    /// The time of the last status change (e.g. permissions, owner or contents)
+    #[serde(with = "optional_rfc3339")]
+    pub changed: Option<SystemTime>,
+    /// The inode number
+    pub inode: Option<u64>,
+    /// The ID of the device containing the entry
+    pub device: Option<u64>,
+    /// The number of hard links to the entry
+    pub links: Option<u64>,
+}
+
+/// Resolves user and group IDs to names, remembering the results
+///
+/// The same few owners show up over and over again in a tree, so every ID is only
+/// looked up once.
+#[derive(Default)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) struct NameCache {
+    users: Mutex<HashMap<u32, Option<String>>>,
+    groups: Mutex<HashMap<u32, Option<String>>>,
+}
+
+impl NameCache {
+    /// Returns the name of the user with the given ID, if there is one
+    #[cfg(unix)]
+    fn user(&self, uid: u32) -> Option<String> {
+        let mut users = self.users.lock().unwrap();
+        users
+            .entry(uid)
+            .or_insert_with(|| {
+                uzers::get_user_by_uid(uid).map(|u| u.name().to_string_lossy().to_string())
+            })
+            .clone()
+    }
+
+    /// Returns the name of the group with the given ID, if there is one
+    #[cfg(unix)]
+    fn group(&self, gid: u32) -> Option<String> {
+        let mut groups = self.groups.lock().unwrap();
+        groups
+            .entry(gid)
+            .or_insert_with(|| {
+                uzers::get_group_by_gid(gid).map(|g| g.name().to_string_lossy().to_string())
+            })
+            .clone()
+    }
+}
+
+impl Metadata {
+    /// Collects the metadata of an entry from its file system metadata
+    #[cfg(unix)]
+    pub(crate) fn collect(metadata: &std::fs::Metadata, names: &NameCache) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        // The status change time is not exposed by `std::fs::Metadata` itself
+        let changed = u64::try_from(metadata.ctime())
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::new(secs, metadata.ctime_nsec() as u32));
+
+        Self {
+            mode: Some(metadata.mode() & 0o7777),
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+            user: names.user(metadata.uid()),
+            group: names.group(metadata.gid()),
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+            changed,
+            inode: Some(metadata.ino()),
+            device: Some(metadata.dev()),
+            links: Some(metadata.nlink()),
+        }
+    }
+
+    /// Collects the metadata of an entry from its file system metadata
+    #[cfg(not(unix))]
+    pub(crate) fn collect(metadata: &std::fs::Metadata, _names: &NameCache) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! This module implements the metadata-based filters, similar to the tests of `find`.
+//!
+//! They only apply to the entries that are not directories, so that the structure of
+//! the tree is preserved (see `Config::prune` to hide the directories left empty).
+
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::source::Stat;
+
+/// A kind of file system entry, as matched by `--type`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FileKind {
+    /// A regular file
+    File,
+    /// A directory
+    Directory,
+    /// A symbolic link
+    Symlink,
+    /// A regular file with any of the execute bits set
+    Executable,
+    /// A named pipe
+    Fifo,
+    /// A Unix domain socket
+    Socket,
+}
+
+impl FileKind {
+    /// Checks whether an entry with the given (non-followed) metadata is of this kind
+    pub fn matches(&self, stat: &dyn Stat) -> bool {
+        match self {
+            Self::Executable => {
+                stat.kind() == Some(Self::File) && stat.mode().is_some_and(|m| m & 0o111 != 0)
+            }
+            kind => stat.kind() == Some(*kind),
+        }
+    }
+}
+
+impl std::str::FromStr for FileKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "f" | "file" => Ok(Self::File),
+            "d" | "dir" | "directory" => Ok(Self::Directory),
+            "l" | "link" | "symlink" => Ok(Self::Symlink),
+            "x" | "executable" => Ok(Self::Executable),
+            "p" | "pipe" | "fifo" => Ok(Self::Fifo),
+            "s" | "socket" => Ok(Self::Socket),
+            e => Err(format!(
+                "Unknown file type: {e} (expected f, d, l, x, p or s)"
+            )),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FileKind {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<FileKind>().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A test on the permission bits of an entry, as matched by `--perm`
+///
+/// Like in `find`, `644` requires exactly these bits, `-644` requires all of them
+/// (and allows more), and `/111` requires any of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PermMatch {
+    /// Exactly these permission bits
+    Exact(u32),
+    /// At least all of these permission bits
+    All(u32),
+    /// Any of these permission bits
+    Any(u32),
+}
+
+impl PermMatch {
+    /// Checks whether the given permission bits pass the test
+    pub fn matches(&self, mode: u32) -> bool {
+        let mode = mode & 0o7777;
+        match *self {
+            Self::Exact(bits) => mode == bits,
+            Self::All(bits) => mode & bits == bits,
+            Self::Any(bits) => bits == 0 || mode & bits != 0,
+        }
+    }
+}
+
+impl std::str::FromStr for PermMatch {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (make, bits): (fn(u32) -> Self, &str) = match s.as_bytes().first() {
+            Some(b'-') => (Self::All, &s[1..]),
+            Some(b'/') => (Self::Any, &s[1..]),
+            _ => (Self::Exact, s),
+        };
+        match u32::from_str_radix(bits, 8) {
+            Ok(bits) if bits <= 0o7777 => Ok(make(bits)),
+            _ => Err(format!(
+                "Invalid permissions: {s} (expected octal bits, e.g. 644, -644 or /111)"
+            )),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PermMatch {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<PermMatch>().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The metadata-based filters that the entries other than directories have to pass
+pub struct Predicates {
+    /// The kinds of entries to show (any of them)
+    types: Vec<FileKind>,
+    /// The minimum size in bytes
+    min_size: Option<u64>,
+    /// The maximum size in bytes
+    max_size: Option<u64>,
+    /// The time after which entries must have been modified
+    newer: Option<SystemTime>,
+    /// The time before which entries must have been modified
+    older: Option<SystemTime>,
+    /// The test on the permission bits
+    perm: Option<PermMatch>,
+    /// The ID of the user that must own the entries
+    uid: Option<u32>,
+    /// The ID of the group that must own the entries
+    gid: Option<u32>,
+}
+
+impl Predicates {
+    /// Creates the predicates from the configuration, or `None` if there are none
+    ///
+    /// The points in time, users and groups are resolved once, up front.
+    pub fn new(cfg: &Config) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let now = SystemTime::now();
+        let predicates = Self {
+            types: cfg.types.clone(),
+            min_size: cfg.min_size,
+            max_size: cfg.max_size,
+            newer: cfg.newer.as_ref().map(|t| t.resolve(now)).transpose()?,
+            older: cfg.older.as_ref().map(|t| t.resolve(now)).transpose()?,
+            perm: cfg.perm,
+            uid: cfg.user.as_deref().map(resolve_user).transpose()?,
+            gid: cfg.group.as_deref().map(resolve_group).transpose()?,
+        };
+        Ok(predicates.is_active().then_some(predicates))
+    }
+
+    /// Whether any predicate is set
+    fn is_active(&self) -> bool {
+        !self.types.is_empty()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer.is_some()
+            || self.older.is_some()
+            || self.perm.is_some()
+            || self.uid.is_some()
+            || self.gid.is_some()
+    }
+
+    /// Checks whether an entry with the given (non-followed) metadata passes all the predicates
+    pub fn matches(&self, stat: &dyn Stat) -> bool {
+        if !self.types.is_empty() && !self.types.iter().any(|kind| kind.matches(stat)) {
+            return false;
+        }
+
+        let size = stat.size();
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        if self.newer.is_some() || self.older.is_some() {
+            let Some(modified) = stat.mtime() else {
+                return false;
+            };
+            if self.newer.is_some_and(|t| modified <= t)
+                || self.older.is_some_and(|t| modified >= t)
+            {
+                return false;
+            }
+        }
+
+        if let Some(perm) = self.perm
+            && !stat.mode().is_some_and(|mode| perm.matches(mode))
+        {
+            return false;
+        }
+
+        let (uid, gid) = stat.owner();
+        if self.uid.is_some_and(|id| uid != Some(id)) || self.gid.is_some_and(|id| gid != Some(id))
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Resolves a user name (or numeric ID) to a user ID
+fn resolve_user(user: &str) -> Result<u32, String> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    #[cfg(unix)]
+    if let Some(found) = uzers::get_user_by_name(user) {
+        return Ok(found.uid());
+    }
+    Err(format!("Unknown user: {user}"))
+}
+
+/// Resolves a group name (or numeric ID) to a group ID
+fn resolve_group(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    #[cfg(unix)]
+    if let Some(found) = uzers::get_group_by_name(group) {
+        return Ok(found.gid());
+    }
+    Err(format!("Unknown group: {group}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use crate::helpers::time::PointInTime;
+
+    /// The metadata of an entry that does not exist on disk
+    struct Fake {
+        kind: FileKind,
+        mode: u32,
+        mtime: SystemTime,
+    }
+
+    impl Stat for Fake {
+        fn kind(&self) -> Option<FileKind> {
+            Some(self.kind)
+        }
+        fn size(&self) -> u64 {
+            0
+        }
+        fn mtime(&self) -> Option<SystemTime> {
+            Some(self.mtime)
+        }
+        fn mode(&self) -> Option<u32> {
+            Some(self.mode)
+        }
+        fn owner(&self) -> (Option<u32>, Option<u32>) {
+            (None, None)
+        }
+    }
+
+    fn fake(kind: FileKind, mode: u32) -> Fake {
+        let mtime = SystemTime::now();
+        Fake { kind, mode, mtime }
+    }
+
+    #[test]
+    fn types() {
+        use FileKind::*;
+        assert!(File.matches(&fake(File, 0o644)));
+        assert!(!File.matches(&fake(Symlink, 0o777)));
+        assert!(Symlink.matches(&fake(Symlink, 0o777)));
+        assert!(Executable.matches(&fake(File, 0o744)));
+        assert!(Executable.matches(&fake(File, 0o601)));
+        assert!(!Executable.matches(&fake(File, 0o644)));
+        // A directory is searchable, not executable
+        assert!(!Executable.matches(&fake(Directory, 0o755)));
+
+        assert_eq!("x".parse(), Ok(Executable));
+        assert_eq!("Pipe".parse(), Ok(Fifo));
+        assert!("b".parse::<FileKind>().is_err());
+    }
+
+    #[test]
+    fn permissions() {
+        let perm = |s: &str| s.parse::<PermMatch>().unwrap();
+        assert!(perm("644").matches(0o100644));
+        assert!(!perm("644").matches(0o755));
+        assert!(perm("-600").matches(0o644));
+        assert!(!perm("-600").matches(0o400));
+        assert!(perm("/111").matches(0o744));
+        assert!(!perm("/111").matches(0o644));
+        assert!(perm("/000").matches(0o644));
+
+        assert!("999".parse::<PermMatch>().is_err());
+        assert!("17777".parse::<PermMatch>().is_err());
+    }
+
+    #[test]
+    fn modification_times() {
+        let hour = Duration::from_secs(3600);
+        let cfg = ConfigBuilder {
+            newer: Some(PointInTime::Ago(2 * hour)),
+            older: Some(PointInTime::Ago(hour)),
+            ..Default::default()
+        }
+        .build();
+        let predicates = Predicates::new(&cfg).unwrap().unwrap();
+        let modified = |ago| Fake {
+            mtime: SystemTime::now() - ago,
+            ..fake(FileKind::File, 0o644)
+        };
+        assert!(predicates.matches(&modified(hour + hour / 2)));
+        assert!(!predicates.matches(&modified(hour / 2)));
+        assert!(!predicates.matches(&modified(3 * hour)));
+
+        let cfg = ConfigBuilder::default().build();
+        assert!(Predicates::new(&cfg).unwrap().is_none());
+    }
+}
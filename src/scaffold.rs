@@ -0,0 +1,627 @@
+//! This module creates a directory structure from a tree diagram, for `fstree scaffold`.
+//!
+//! A diagram is read in the format that `TextFormatter` writes without colors: one entry
+//! per line, with the names of the directories ending in `/`. The columns before the
+//! branches and the details after the names, e.g. ` (29B)`, are skipped. It can also be
+//! a snapshot (see the `snapshot` module), whose tree is recreated instead.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::Config;
+use crate::formatter::Charset;
+use crate::helpers;
+use crate::snapshot::Snapshot;
+use crate::tree::{NodeType, TreeNode};
+
+/// The indentation below the last entry of a branch, which does not depend on the prefixes
+const LAST_CHILD_PREFIX: &str = "    ";
+
+/// What an entry of a layout is
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EntryKind {
+    /// A directory
+    Directory,
+    /// An empty file
+    File,
+    /// A symbolic link to the given target
+    SymbolicLink(PathBuf),
+}
+
+/// An entry of a layout
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Entry {
+    /// The path of the entry, relative to the directory the layout is created in
+    pub path: PathBuf,
+    /// What the entry is
+    pub kind: EntryKind,
+}
+
+/// The directories, files and links to create, parents first
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Layout {
+    /// The entries, in the order of the diagram
+    pub entries: Vec<Entry>,
+}
+
+/// What creating an entry does to the file system
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Action {
+    /// Creates the entry, which does not exist yet
+    Create,
+    /// Leaves an existing directory as it is
+    Keep,
+    /// Replaces an existing file (only with `force`)
+    Overwrite,
+}
+
+impl Action {
+    /// Returns the verb shown for the action, e.g. in a dry run
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Keep => "keep",
+            Self::Overwrite => "overwrite",
+        }
+    }
+}
+
+/// A single change to the file system, planned by `Layout::plan`
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Step {
+    /// What is done
+    pub action: Action,
+    /// The path of the entry
+    pub path: PathBuf,
+    /// What the entry is
+    pub kind: EntryKind,
+}
+
+impl Layout {
+    /// Reads a layout from a file, either a tree diagram or a snapshot
+    ///
+    /// A file is read as a snapshot if it starts with `{`, like JSON does.
+    pub fn load(path: &Path, cfg: &Config) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        if text.trim_start().starts_with('{') {
+            return Self::from_tree(&Snapshot::load(path)?.root);
+        }
+        Self::parse(&text, cfg).map_err(|e| format!("{}: {e}", path.display()).into())
+    }
+
+    /// Parses a tree diagram
+    ///
+    /// The branches can be drawn with the configured prefixes, or with the default ones
+    /// of any charset, e.g. `├── ` or `|-- `. The first line is the root, i.e. the
+    /// directory that the layout is created in, unless it has a branch too. The empty
+    /// lines, and the lines of Markdown code fences, are skipped.
+    ///
+    /// The columns that the formatter prints before the branches (see `Config::columns`)
+    /// are skipped, as long as they are as wide on every line, and so are the details it
+    /// prints after the names (see `strip_details`).
+    pub fn parse(text: &str, cfg: &Config) -> Result<Self, String> {
+        let mut glyphs = vec![[
+            cfg.prefix.as_str(),
+            cfg.last_prefix.as_str(),
+            cfg.child_prefix.as_str(),
+        ]];
+        for charset in [Charset::Utf8, Charset::Ascii] {
+            let defaults = [
+                charset.prefix(),
+                charset.last_prefix(),
+                charset.child_prefix(),
+            ];
+            if !glyphs.contains(&defaults) {
+                glyphs.push(defaults);
+            }
+        }
+
+        // The first entry is a child of the root, so anything before its branch that is
+        // not indentation is the columns
+        let columns_width = text
+            .lines()
+            .find_map(|line| Some((branch_start(line, &glyphs)?, line)))
+            .filter(|&(start, line)| {
+                start > 0 && !glyphs.iter().any(|g| parse_branch(line, g).is_some())
+            })
+            .map_or(0, |(start, line)| line[..start].chars().count());
+
+        let mut layout = Self::default();
+        // The path of the directory at each depth that entries can be added to, the
+        // root being the empty path
+        let mut dirs = vec![PathBuf::new()];
+        let mut first = true;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.trim().is_empty() || line.starts_with("```") {
+                continue;
+            }
+            let line = match line.char_indices().nth(columns_width) {
+                Some((start, _)) => &line[start..],
+                None if columns_width > 0 => "",
+                None => line,
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let n = i + 1;
+            let Some((depth, name)) = glyphs.iter().find_map(|g| parse_branch(line, g)) else {
+                if first && !line.starts_with(char::is_whitespace) {
+                    first = false;
+                    continue;
+                }
+                return Err(format!("line {n}: expected a branch before `{line}`"));
+            };
+            first = false;
+
+            let (name, kind) = parse_name(strip_details(name));
+            if !is_valid_name(name) {
+                return Err(format!("line {n}: invalid name `{name}`"));
+            }
+            if dirs.len() < depth {
+                return Err(format!("line {n}: `{name}` has no parent directory"));
+            }
+            dirs.truncate(depth);
+            let path = dirs[depth - 1].join(name);
+            if kind == EntryKind::Directory {
+                dirs.push(path.clone());
+            }
+            layout.entries.push(Entry { path, kind });
+        }
+        Ok(layout)
+    }
+
+    /// Builds the layout of a tree, e.g. of a snapshot, whose root is the directory the
+    /// layout is created in
+    pub fn from_tree(root: &TreeNode) -> Result<Self, Box<dyn Error>> {
+        let mut layout = Self::default();
+        for child in &root.children {
+            layout.add_node(child, Path::new(""))?;
+        }
+        Ok(layout)
+    }
+
+    /// Adds a node of a tree, and its descendants, to the layout
+    fn add_node(&mut self, node: &TreeNode, parent: &Path) -> Result<(), Box<dyn Error>> {
+        if !is_valid_name(&node.name) {
+            return Err(format!("invalid name `{}`", node.name).into());
+        }
+        let path = parent.join(&node.name);
+        let kind = match (&node.node_type, &node.target) {
+            (NodeType::Directory, _) => EntryKind::Directory,
+            (NodeType::SymbolicLink, Some(target)) => EntryKind::SymbolicLink(target.clone()),
+            _ => EntryKind::File,
+        };
+        self.entries.push(Entry {
+            path: path.clone(),
+            kind,
+        });
+        for child in &node.children {
+            self.add_node(child, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Works out what creating the layout in a directory would do, without changing anything
+    ///
+    /// Existing directories are kept. Existing files and links are only replaced with
+    /// `force`, and an existing directory is never replaced with a file, so that nothing
+    /// is lost by mistake. All the conflicts are found before anything is created.
+    pub fn plan(&self, into: &Path, force: bool) -> Result<Vec<Step>, Box<dyn Error>> {
+        let mut steps = Vec::new();
+        match fs::metadata(into) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return Err(format!("{} is not a directory", into.display()).into()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => steps.push(Step {
+                action: Action::Create,
+                path: into.to_path_buf(),
+                kind: EntryKind::Directory,
+            }),
+            Err(e) => return Err(format!("{}: {e}", into.display()).into()),
+        }
+
+        let mut planned: HashMap<&Path, &EntryKind> = HashMap::new();
+        for entry in &self.entries {
+            // An entry that appears twice is only created once
+            match planned.insert(&entry.path, &entry.kind) {
+                Some(kind) if *kind == entry.kind => continue,
+                Some(_) => {
+                    return Err(format!(
+                        "{} appears twice in the layout, as different kinds of entries",
+                        entry.path.display()
+                    )
+                    .into());
+                }
+                None => {}
+            }
+
+            let path = into.join(&entry.path);
+            let existing = match fs::symlink_metadata(&path) {
+                Ok(metadata) => Some(metadata),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(format!("{}: {e}", path.display()).into()),
+            };
+            let action = match existing {
+                None => Action::Create,
+                Some(metadata) if metadata.is_dir() => match entry.kind {
+                    EntryKind::Directory => Action::Keep,
+                    _ => return Err(format!("{} is a directory", path.display()).into()),
+                },
+                Some(_) if force => Action::Overwrite,
+                Some(_) => {
+                    return Err(format!(
+                        "{} already exists (use --force to overwrite it)",
+                        path.display()
+                    )
+                    .into());
+                }
+            };
+            steps.push(Step {
+                action,
+                path,
+                kind: entry.kind.clone(),
+            });
+        }
+        Ok(steps)
+    }
+}
+
+impl Step {
+    /// Makes the change to the file system
+    pub fn apply(&self) -> io::Result<()> {
+        if self.action == Action::Keep {
+            return Ok(());
+        }
+        if self.action == Action::Overwrite {
+            // Remove rather than truncate, so that a link is replaced instead of followed
+            fs::remove_file(&self.path)?;
+        }
+        match &self.kind {
+            EntryKind::Directory => fs::create_dir_all(&self.path),
+            EntryKind::File => fs::File::create_new(&self.path).map(|_| ()),
+            EntryKind::SymbolicLink(target) => symlink(target, &self.path),
+        }
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", self.path.display())))
+    }
+}
+
+/// Strips the branch off a line drawn with the given prefix, last prefix and child prefix
+///
+/// Returns the depth of the entry, 1 for a child of the root, along with the rest of the line.
+fn parse_branch<'a>(
+    line: &'a str,
+    [prefix, last_prefix, child_prefix]: &[&str; 3],
+) -> Option<(usize, &'a str)> {
+    let mut depth = 1;
+    let mut rest = line;
+    loop {
+        if let Some(name) = rest
+            .strip_prefix(prefix)
+            .or_else(|| rest.strip_prefix(last_prefix))
+        {
+            return Some((depth, name));
+        }
+        rest = rest
+            .strip_prefix(child_prefix)
+            .or_else(|| rest.strip_prefix(LAST_CHILD_PREFIX))?;
+        depth += 1;
+    }
+}
+
+/// Returns the byte position of the first branch of a line, drawn with any of the given
+/// prefixes
+fn branch_start(line: &str, glyphs: &[[&str; 3]]) -> Option<usize> {
+    glyphs
+        .iter()
+        .flat_map(|[prefix, last_prefix, _]| [prefix, last_prefix])
+        .filter(|glyph| !glyph.is_empty())
+        .filter_map(|glyph| line.find(glyph))
+        .min()
+}
+
+/// Strips the details that `TextFormatter` prints after a name, e.g. ` (29B)`,
+/// ` (3 matches)`, ` (+8B)`, ` (from old/path)`, ` (dupe #1, 12B reclaimable)` or
+/// ` [error: …]`
+///
+/// Other parentheses are kept, e.g. in `notes (1)`, since a size always has a unit.
+fn strip_details(mut name: &str) -> &str {
+    if let Some(start) = name.rfind(" [error: ")
+        && name.ends_with(']')
+    {
+        name = &name[..start];
+    }
+    while let Some((rest, details)) = split_parenthesized(name) {
+        let is_size = |s: &str| {
+            s.ends_with(|c: char| c.is_ascii_alphabetic()) && helpers::bytes::parse(s).is_ok()
+        };
+        let is_count = |s: &str| {
+            s.strip_suffix(" matches")
+                .or_else(|| s.strip_suffix(" match"))
+                .is_some_and(|n| n.parse::<usize>().is_ok())
+        };
+        let is_detail = is_size(details)
+            || details.strip_prefix(['+', '-']).is_some_and(is_size)
+            || is_count(details)
+            || details.starts_with("from ")
+            || details.starts_with("dupe #");
+        if !is_detail {
+            break;
+        }
+        name = rest;
+    }
+    name
+}
+
+/// Splits a trailing ` (…)` off a string, with balanced parentheses inside
+fn split_parenthesized(s: &str) -> Option<(&str, &str)> {
+    let inner = s.strip_suffix(')')?;
+    let mut depth = 0;
+    for (i, c) in inner.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' if depth > 0 => depth -= 1,
+            '(' => return Some((inner[..i].strip_suffix(' ')?, &inner[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits what follows the branch into the name of the entry and its kind, from the
+/// trailing `/` of a directory or the ` -> target` of a symbolic link
+fn parse_name(name: &str) -> (&str, EntryKind) {
+    if let Some(dir) = name.strip_suffix('/') {
+        return (dir, EntryKind::Directory);
+    }
+    match name.split_once(" -> ") {
+        Some((link, target)) => (link, EntryKind::SymbolicLink(PathBuf::from(target))),
+        None => (name, EntryKind::File),
+    }
+}
+
+/// Checks that a name is a single component of a path, so that the layout cannot
+/// reach outside the directory it is created in
+fn is_valid_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains('/')
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+/// Creates a symbolic link
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Creates a symbolic link
+#[cfg(not(unix))]
+fn symlink(_target: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::columns::Column;
+    use crate::config::ConfigBuilder;
+    use crate::formatter::{Formatter, TextFormatter};
+
+    /// Draws the tree of a directory like `fstree --no-color`, without the root line,
+    /// whose name differs from one directory to the next
+    fn diagram(root: &Path, builder: ConfigBuilder) -> String {
+        let cfg = ConfigBuilder {
+            root: Some(root.to_path_buf()),
+            no_color: true,
+            ..builder
+        }
+        .build();
+        let (tree, stats) = crate::build(&cfg).unwrap();
+        let mut out = Vec::new();
+        TextFormatter::new(&cfg, &mut out)
+            .format(&tree, &stats)
+            .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        text.split_once('\n').unwrap().1.to_string()
+    }
+
+    /// Creates a layout in a directory, returning the steps taken
+    fn create(layout: &Layout, into: &Path, force: bool) -> Result<Vec<Step>, Box<dyn Error>> {
+        let steps = layout.plan(into, force)?;
+        for step in &steps {
+            step.apply()?;
+        }
+        Ok(steps)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recreates_the_tree_of_a_diagram() {
+        let source = tempfile::tempdir().unwrap();
+        for dir in ["docs", "src/handlers", "src/empty"] {
+            std::fs::create_dir_all(source.path().join(dir)).unwrap();
+        }
+        for file in [
+            "Cargo.toml",
+            "src/main.rs",
+            "src/handlers/mod.rs",
+            "docs/guide.md",
+        ] {
+            std::fs::write(source.path().join(file), "").unwrap();
+        }
+        std::os::unix::fs::symlink("docs/guide.md", source.path().join("GUIDE")).unwrap();
+
+        // The charset, or the custom prefix, last prefix and child prefix
+        let styles = [
+            (Some(Charset::Utf8), None),
+            (Some(Charset::Ascii), None),
+            (None, Some(["+- ", "\\- ", "|  "])),
+        ];
+        for (charset, prefixes) in styles {
+            let style = || ConfigBuilder {
+                charset,
+                prefix: prefixes.map(|p| p[0].to_string()),
+                last_prefix: prefixes.map(|p| p[1].to_string()),
+                child_prefix: prefixes.map(|p| p[2].to_string()),
+                ..Default::default()
+            };
+            let expected = diagram(source.path(), style());
+            assert!(expected.contains("GUIDE -> docs/guide.md"), "{expected}");
+            let layout = Layout::parse(&format!("project/\n{expected}"), &style().build()).unwrap();
+
+            let into = tempfile::tempdir().unwrap();
+            create(&layout, into.path(), false).unwrap();
+            assert_eq!(
+                diagram(into.path(), style()),
+                expected,
+                "{charset:?} {prefixes:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn skips_the_columns_and_the_details_of_a_diagram() {
+        let source = tempfile::tempdir().unwrap();
+        for dir in ["src/handlers", "src/empty"] {
+            std::fs::create_dir_all(source.path().join(dir)).unwrap();
+        }
+        for file in ["notes (1)", "src/main.rs", "src/handlers/mod.rs"] {
+            std::fs::write(source.path().join(file), "todo\ntodo\n").unwrap();
+        }
+
+        let expected = diagram(source.path(), ConfigBuilder::default());
+        let annotated = diagram(
+            source.path(),
+            ConfigBuilder {
+                columns: Some(vec![Column::Perms, Column::Links, Column::Size]),
+                size: true,
+                contains: Some("todo".to_string()),
+                count_matches: true,
+                ..Default::default()
+            },
+        );
+        assert!(
+            annotated.contains("mod.rs (10B) (2 matches)"),
+            "{annotated}"
+        );
+        let cfg = ConfigBuilder::default().build();
+        let layout = Layout::parse(&format!("project/\n{annotated}"), &cfg).unwrap();
+
+        let into = tempfile::tempdir().unwrap();
+        create(&layout, into.path(), false).unwrap();
+        assert_eq!(diagram(into.path(), ConfigBuilder::default()), expected);
+    }
+
+    #[test]
+    fn strips_only_the_details_that_the_formatter_prints() {
+        let cases = [
+            ("src/ (29B)", "src/"),
+            ("main.rs (1.50KB) (1 match)", "main.rs"),
+            ("main.rs (+8B) (from old/main.rs)", "main.rs"),
+            ("copy (12B) (dupe #1, 12B reclaimable)", "copy"),
+            ("link -> target (7B)", "link -> target"),
+            ("secret [error: permission denied]", "secret"),
+            ("notes (1)", "notes (1)"),
+            ("notes (draft)", "notes (draft)"),
+            ("notes (1) (3B)", "notes (1)"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(strip_details(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_names_that_reach_outside_the_directory() {
+        let cfg = ConfigBuilder::default().build();
+        for name in ["..", "../", ".", "a/b", "a/b/", "/etc", "../x -> y"] {
+            let text = format!("root/\n└── {name}\n");
+            let error = Layout::parse(&text, &cfg).unwrap_err();
+            assert!(error.contains("invalid name"), "{name}: {error}");
+        }
+    }
+
+    #[test]
+    fn rejects_branches_without_a_parent_directory() {
+        let cfg = ConfigBuilder::default().build();
+        for text in ["root/\n├── a\n│   └── b\n", "root/\n    └── a/\n", "a\nb\n"]
+        {
+            assert!(Layout::parse(text, &cfg).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn keeps_directories_and_only_overwrites_files_with_force() {
+        let cfg = ConfigBuilder::default().build();
+        let layout = Layout::parse("root/\n├── dir/\n│   └── file\n└── other\n", &cfg).unwrap();
+        let into = tempfile::tempdir().unwrap();
+        std::fs::create_dir(into.path().join("dir")).unwrap();
+        std::fs::write(into.path().join("dir/file"), "contents").unwrap();
+
+        // Nothing is created when a file is in the way
+        let error = create(&layout, into.path(), false).unwrap_err();
+        assert!(error.to_string().contains("already exists"), "{error}");
+        assert!(!into.path().join("other").exists());
+
+        let steps = create(&layout, into.path(), true).unwrap();
+        let actions: Vec<_> = steps.iter().map(|step| step.action).collect();
+        assert_eq!(actions, [Action::Keep, Action::Overwrite, Action::Create]);
+        assert_eq!(std::fs::read(into.path().join("dir/file")).unwrap(), b"");
+        assert!(into.path().join("other").is_file());
+    }
+
+    #[test]
+    fn never_replaces_a_directory_with_a_file_but_replaces_a_file_with_force() {
+        let cfg = ConfigBuilder::default().build();
+        let layout = Layout::parse("root/\n├── a\n└── b/\n", &cfg).unwrap();
+        let into = tempfile::tempdir().unwrap();
+        std::fs::create_dir(into.path().join("a")).unwrap();
+        std::fs::write(into.path().join("b"), "").unwrap();
+
+        for force in [false, true] {
+            let error = layout.plan(into.path(), force).unwrap_err();
+            assert!(error.to_string().contains("is a directory"), "{error}");
+        }
+        // A file in the way of a directory is only replaced with `force`
+        std::fs::remove_dir(into.path().join("a")).unwrap();
+        let error = layout.plan(into.path(), false).unwrap_err();
+        assert!(error.to_string().contains("already exists"), "{error}");
+        create(&layout, into.path(), true).unwrap();
+        assert!(into.path().join("a").is_file());
+        assert!(into.path().join("b").is_dir());
+    }
+
+    #[test]
+    fn creates_the_directory_it_is_given() {
+        let cfg = ConfigBuilder::default().build();
+        let layout = Layout::parse("root/\n└── a\n", &cfg).unwrap();
+        let parent = tempfile::tempdir().unwrap();
+        let into = parent.path().join("new");
+        let steps = create(&layout, &into, false).unwrap();
+        assert_eq!(steps[0].path, into);
+        assert!(into.join("a").is_file());
+
+        std::fs::write(parent.path().join("file"), "").unwrap();
+        let error = layout.plan(&parent.path().join("file"), true).unwrap_err();
+        assert!(error.to_string().contains("is not a directory"), "{error}");
+    }
+
+    #[test]
+    fn conflicting_entries_are_rejected() {
+        let cfg = ConfigBuilder::default().build();
+        let layout = Layout::parse("root/\n├── a\n└── a/\n", &cfg).unwrap();
+        let into = tempfile::tempdir().unwrap();
+        let error = layout.plan(into.path(), false).unwrap_err();
+        assert!(error.to_string().contains("appears twice"), "{error}");
+    }
+}
@@ -0,0 +1,139 @@
+//! This module searches the contents of files, for `--contains`.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use regex::bytes::Regex;
+
+/// How many bytes at the start of a file are checked for a NUL byte, like git and grep do
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+/// Searches the contents of files for a regular expression
+///
+/// Files are read line by line, so a match cannot span several lines.
+pub struct ContentSearch {
+    /// The pattern to search for
+    regex: Regex,
+    /// Whether to search binary files too
+    binary: bool,
+}
+
+impl ContentSearch {
+    /// Creates a new search for the given pattern
+    pub fn new(pattern: &str, binary: bool) -> Result<Self, String> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| format!("Invalid regular expression `{pattern}`:\n{e}"))?;
+        Ok(Self { regex, binary })
+    }
+
+    /// Searches a file, returning the number of matches
+    ///
+    /// Without `count`, the search stops at the first match, so the result is at most 1.
+    /// Returns `None` for binary files (those with a NUL byte near the start), unless
+    /// binary files are searched too.
+    pub fn search(&self, path: &Path, count: bool) -> io::Result<Option<usize>> {
+        self.search_reader(File::open(path)?, count)
+    }
+
+    /// Searches the contents read from a reader, like `search`
+    pub fn search_reader(&self, mut reader: impl Read, count: bool) -> io::Result<Option<usize>> {
+        let mut head = Vec::with_capacity(BINARY_CHECK_LEN);
+        (&mut reader)
+            .take(BINARY_CHECK_LEN as u64)
+            .read_to_end(&mut head)?;
+        if !self.binary && head.contains(&0) {
+            return Ok(None);
+        }
+
+        let mut reader = BufReader::new(head.as_slice().chain(reader));
+        let mut line = Vec::new();
+        let mut matches = 0;
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            if count {
+                matches += self.regex.find_iter(&line).count();
+            } else if self.regex.is_match(&line) {
+                return Ok(Some(1));
+            }
+            line.clear();
+        }
+        Ok(Some(matches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    #[test]
+    fn counts_every_match_on_every_line() {
+        let search = ContentSearch::new("fn ", false).unwrap();
+        let text = "fn a() {}\nfn b() { fn c() {} }\nstruct D;\n";
+        assert_eq!(
+            search.search_reader(text.as_bytes(), true).unwrap(),
+            Some(3)
+        );
+        // Without counting, the search stops at the first match
+        assert_eq!(
+            search.search_reader(text.as_bytes(), false).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            search.search_reader("struct D;".as_bytes(), true).unwrap(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn skips_binary_files_unless_asked() {
+        let mut contents = b"fn main() {}\n".to_vec();
+        contents.extend_from_slice(&[0, 1, 2]);
+        let search = ContentSearch::new("main", false).unwrap();
+        assert_eq!(
+            search.search_reader(contents.as_slice(), true).unwrap(),
+            None
+        );
+        let search = ContentSearch::new("main", true).unwrap();
+        assert_eq!(
+            search.search_reader(contents.as_slice(), true).unwrap(),
+            Some(1)
+        );
+
+        // A NUL byte past the start does not make a file binary
+        let mut contents = vec![b'a'; BINARY_CHECK_LEN];
+        contents.extend_from_slice(b"\0main");
+        let search = ContentSearch::new("main", false).unwrap();
+        assert_eq!(
+            search.search_reader(contents.as_slice(), true).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn prune_hides_the_directories_without_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["hits", "misses"] {
+            std::fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        std::fs::write(dir.path().join("hits/a.txt"), "todo\ntodo: todo\n").unwrap();
+        std::fs::write(dir.path().join("hits/b.txt"), "done\n").unwrap();
+        std::fs::write(dir.path().join("misses/c.txt"), "done\n").unwrap();
+
+        let cfg = ConfigBuilder {
+            root: Some(dir.path().to_path_buf()),
+            contains: Some("todo".to_string()),
+            count_matches: true,
+            prune: true,
+            ..Default::default()
+        }
+        .build();
+        let (tree, _) = crate::build(&cfg).unwrap();
+        assert_eq!(tree.children.len(), 1);
+        let hits = &tree.children[0];
+        assert_eq!(hits.name, "hits");
+        assert_eq!(hits.children.len(), 1);
+        assert_eq!(hits.children[0].name, "a.txt");
+        assert_eq!(hits.children[0].matches, Some(3));
+    }
+}
@@ -0,0 +1,113 @@
+//! This module saves trees to files as versioned snapshots, and loads them back, so that
+//! a tree can be compared with what it used to be (see the `diff` module).
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::hash::HashAlgorithm;
+use crate::tree::TreeNode;
+
+/// The version of the snapshot format, which is bumped on incompatible changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A tree saved with the details needed to compare it later on
+#[derive(Serialize, Deserialize, Debug)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// The version of the snapshot format (see `SNAPSHOT_VERSION`)
+    pub version: u32,
+    /// When the snapshot was taken
+    #[serde(
+        serialize_with = "crate::helpers::time::serialize_rfc3339",
+        deserialize_with = "crate::helpers::time::deserialize_rfc3339"
+    )]
+    pub created: SystemTime,
+    /// The algorithm of the digests of the files, if they were computed
+    pub hash: Option<HashAlgorithm>,
+    /// The root of the tree
+    pub root: TreeNode,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the tree rooted at `cfg.root`
+    ///
+    /// The files can only be compared by size unless their modification times were
+    /// collected (see `Config::metadata`), or their digests (see `Config::hash`).
+    pub fn take(cfg: &Config) -> Result<Self, Box<dyn Error>> {
+        let (root, _) = crate::build(cfg)?;
+        Ok(Self {
+            version: SNAPSHOT_VERSION,
+            created: SystemTime::now(),
+            hash: cfg.hash_algorithm(),
+            root,
+        })
+    }
+
+    /// Writes the snapshot to a file, as pretty-printed JSON
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, self).map_err(io::Error::other)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    /// Reads a snapshot from a file
+    ///
+    /// The version is checked first, so that a snapshot written by a newer version is
+    /// reported as such, rather than as invalid.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let invalid = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+        let file = File::open(path).map_err(|e| invalid(&e))?;
+        let value: serde_json::Value =
+            serde_json::from_reader(BufReader::new(file)).map_err(|e| invalid(&e))?;
+
+        match value.get("version").and_then(serde_json::Value::as_u64) {
+            Some(version) if version == u64::from(SNAPSHOT_VERSION) => {}
+            Some(version) => {
+                return Err(invalid(&format!(
+                    "unsupported snapshot version {version} (expected {SNAPSHOT_VERSION})"
+                ))
+                .into());
+            }
+            None => return Err(invalid(&"not a snapshot (no version)").into()),
+        }
+        Ok(serde_json::from_value(value).map_err(|e| invalid(&e))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    #[test]
+    fn only_snapshots_of_this_version_are_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file"), "contents").unwrap();
+        let cfg = ConfigBuilder {
+            root: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        }
+        .build();
+        let path = dir.path().join("snapshot.json");
+        Snapshot::take(&cfg).unwrap().save(&path).unwrap();
+        let snapshot = Snapshot::load(&path).unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert!(snapshot.root.children.iter().any(|c| c.name == "file"));
+
+        let error = |json: &str| {
+            std::fs::write(&path, json).unwrap();
+            Snapshot::load(&path).unwrap_err().to_string()
+        };
+        let newer = error(r#"{"version": 99, "root": "from a later format"}"#);
+        assert!(newer.contains("unsupported snapshot version 99"), "{newer}");
+        let other = error(r#"{"name": "not a snapshot"}"#);
+        assert!(other.contains("no version"), "{other}");
+    }
+}
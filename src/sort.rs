@@ -0,0 +1,237 @@
+//! This module determines the order of the entries of each directory.
+//!
+//! The entries are sorted as soon as a directory has been read, so every formatter
+//! sees them in the same order, regardless of the order of the file system.
+
+use std::cmp::Ordering;
+
+use crate::config::Config;
+use crate::tree::{NodeType, TreeNode};
+
+/// The key to sort the entries of a directory by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SortKey {
+    /// Keep the order in which the file system returned the entries
+    None,
+    /// By name, ignoring case and comparing numbers by value (`file2` before `file10`)
+    Name,
+    /// By size, largest first
+    Size,
+    /// By modification time, newest first
+    Mtime,
+    /// By extension, then by name
+    Extension,
+    /// By type (directories, symbolic links, files), then by name
+    Type,
+    /// By the date of the last commit that touched the entry, newest first
+    Commit,
+}
+
+impl SortKey {
+    /// Whether sorting by this key needs the metadata collected with `Config::metadata`
+    pub fn needs_metadata(&self) -> bool {
+        matches!(self, Self::Mtime)
+    }
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "name" => Ok(Self::Name),
+            "size" => Ok(Self::Size),
+            "mtime" | "time" | "modified" => Ok(Self::Mtime),
+            "extension" | "ext" => Ok(Self::Extension),
+            "type" => Ok(Self::Type),
+            "commit" | "commit-date" | "last-commit" => Ok(Self::Commit),
+            e => Err(format!("Unknown sort key: {e}")),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SortKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<SortKey>().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Where directories are placed among the other entries of a directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupDirs {
+    /// Directories are sorted along with all the other entries
+    Mixed,
+    /// Directories come before all the other entries
+    First,
+    /// Directories come after all the other entries
+    Last,
+}
+
+impl std::str::FromStr for GroupDirs {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mixed" | "none" => Ok(Self::Mixed),
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            e => Err(format!("Unknown directory grouping: {e}")),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for GroupDirs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<GroupDirs>().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Compares two entries of the same directory according to the configuration
+///
+/// Entries that are equal by the sort key are ordered by name, so that the order
+/// never depends on the file system.
+pub fn compare(a: &TreeNode, b: &TreeNode, cfg: &Config) -> Ordering {
+    let is_dir = |node: &TreeNode| matches!(node.node_type, NodeType::Directory);
+    let group = match cfg.group_dirs {
+        GroupDirs::Mixed => Ordering::Equal,
+        GroupDirs::First => is_dir(b).cmp(&is_dir(a)),
+        GroupDirs::Last => is_dir(a).cmp(&is_dir(b)),
+    };
+
+    let order = match cfg.sort {
+        SortKey::None => return group,
+        SortKey::Name => compare_names(a, b),
+        SortKey::Size => b.size.cmp(&a.size).then_with(|| compare_names(a, b)),
+        SortKey::Mtime => {
+            let mtime = |node: &TreeNode| node.metadata.as_ref().and_then(|m| m.modified);
+            mtime(b).cmp(&mtime(a)).then_with(|| compare_names(a, b))
+        }
+        SortKey::Extension => {
+            let extension = |node: &TreeNode| {
+                node.path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_string())
+            };
+            match (extension(a), extension(b)) {
+                (Some(x), Some(y)) => natural_cmp(&x, &y),
+                (x, y) => x.is_some().cmp(&y.is_some()),
+            }
+            .then_with(|| compare_names(a, b))
+        }
+        SortKey::Commit => {
+            let date = |node: &TreeNode| node.commit.as_ref().map(|c| c.date);
+            date(b).cmp(&date(a)).then_with(|| compare_names(a, b))
+        }
+        SortKey::Type => type_rank(&a.node_type)
+            .cmp(&type_rank(&b.node_type))
+            .then_with(|| compare_names(a, b)),
+    };
+
+    group.then(if cfg.reverse { order.reverse() } else { order })
+}
+
+/// Compares the names of two entries naturally, falling back to their exact names
+fn compare_names(a: &TreeNode, b: &TreeNode) -> Ordering {
+    natural_cmp(&a.name, &b.name).then_with(|| a.name.cmp(&b.name))
+}
+
+/// Returns the position of a node type when sorting by type
+fn type_rank(node_type: &NodeType) -> u8 {
+    match node_type {
+        NodeType::Directory => 0,
+        NodeType::SymbolicLink => 1,
+        NodeType::File => 2,
+        _ => 3,
+    }
+}
+
+/// Compares two strings case-insensitively, with runs of digits compared by their value
+///
+/// This is the order people expect for versions and numbered files, e.g.
+/// `v1.9` < `v1.10` and `File2` < `file10`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Compare by value (ignoring leading zeros), then by number of digits
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let order = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let order = x.to_lowercase().cmp(y.to_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Consumes a run of ASCII digits from the iterator
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        number.push(c);
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        use Ordering::*;
+        let cases = [
+            // Runs of digits compare by value
+            ("file2", "file10", Less),
+            ("v1.9", "v1.10", Less),
+            ("99999999999999999999", "100000000000000000000", Less),
+            // Leading zeros only break ties between equal values
+            ("file002", "file10", Less),
+            ("file02", "file2", Greater),
+            ("file0", "file00", Less),
+            // Case is ignored
+            ("File2", "file10", Less),
+            ("a", "B", Less),
+            ("README", "readme", Equal),
+            // Non-ASCII letters
+            ("Été", "été", Equal),
+            ("z", "é", Less),
+            ("Ω10", "ω9", Greater),
+            // Equal numeric prefixes fall through to the rest of the name
+            ("1a", "1b", Less),
+            ("10", "10x", Less),
+            ("2.txt", "2.txt", Equal),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(natural_cmp(a, b), expected, "{a} vs {b}");
+            assert_eq!(natural_cmp(b, a), expected.reverse(), "{b} vs {a}");
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! This module abstracts where the entries of a tree are read from: the file system, or
+//! a tree stored in a git commit (see `Config::rev`).
+
+use std::fs;
+use std::time::SystemTime;
+
+use crate::git::GitTree;
+use crate::predicate::FileKind;
+
+/// Where the entries of a tree are read from
+pub enum Source {
+    /// The directories and files on disk
+    FileSystem,
+    /// A tree read from the object database of a git repository
+    Git(Box<GitTree>),
+}
+
+/// The metadata of an entry that the filters are evaluated against, whatever its source
+pub trait Stat {
+    /// Returns the type of the entry (never `FileKind::Executable`), or `None` for the
+    /// other types, e.g. devices
+    fn kind(&self) -> Option<FileKind>;
+
+    /// Returns the size in bytes
+    fn size(&self) -> u64;
+
+    /// Returns the time of the last modification, if known
+    fn mtime(&self) -> Option<SystemTime>;
+
+    /// Returns the permission bits, if known
+    fn mode(&self) -> Option<u32>;
+
+    /// Returns the user and group IDs of the owner, if known
+    fn owner(&self) -> (Option<u32>, Option<u32>);
+}
+
+impl Stat for fs::Metadata {
+    fn kind(&self) -> Option<FileKind> {
+        let file_type = self.file_type();
+        if file_type.is_file() {
+            return Some(FileKind::File);
+        } else if file_type.is_dir() {
+            return Some(FileKind::Directory);
+        } else if file_type.is_symlink() {
+            return Some(FileKind::Symlink);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() {
+                return Some(FileKind::Fifo);
+            } else if file_type.is_socket() {
+                return Some(FileKind::Socket);
+            }
+        }
+        None
+    }
+
+    fn size(&self) -> u64 {
+        self.len()
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        self.modified().ok()
+    }
+
+    #[cfg(unix)]
+    fn mode(&self) -> Option<u32> {
+        use std::os::unix::fs::PermissionsExt;
+        Some(self.permissions().mode())
+    }
+
+    #[cfg(not(unix))]
+    fn mode(&self) -> Option<u32> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn owner(&self) -> (Option<u32>, Option<u32>) {
+        use std::os::unix::fs::MetadataExt;
+        (Some(self.uid()), Some(self.gid()))
+    }
+
+    #[cfg(not(unix))]
+    fn owner(&self) -> (Option<u32>, Option<u32>) {
+        (None, None)
+    }
+}
+
+impl<T: Stat + ?Sized> Stat for &T {
+    fn kind(&self) -> Option<FileKind> {
+        (**self).kind()
+    }
+
+    fn size(&self) -> u64 {
+        (**self).size()
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        (**self).mtime()
+    }
+
+    fn mode(&self) -> Option<u32> {
+        (**self).mode()
+    }
+
+    fn owner(&self) -> (Option<u32>, Option<u32>) {
+        (**self).owner()
+    }
+}
@@ -1,30 +1,74 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
 use serde::Serialize;
 
 /// Statistics collected during tree traversal
+///
+/// The counters are atomic so that they can be updated from several threads
+/// while the tree is being built in parallel.
 #[derive(Default, Serialize)]
 pub struct Statistics {
     /// The total count of directories
-    dirs: usize,
+    dirs: AtomicUsize,
     /// The total count of files
-    files: usize,
+    files: AtomicUsize,
     /// The total byte count
-    bytes: u64,
+    bytes: AtomicU64,
+    /// The total count of entries that could not be read
+    errors: AtomicUsize,
+    /// The total count of entries hidden by the filters
+    filtered: AtomicUsize,
 }
 
 impl Statistics {
+    /// Returns the total count of directories
+    pub fn dirs(&self) -> usize {
+        self.dirs.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total count of files
+    pub fn files(&self) -> usize {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total byte count
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total count of entries that could not be read
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total count of entries hidden by the filters
+    pub fn filtered(&self) -> usize {
+        self.filtered.load(Ordering::Relaxed)
+    }
+
     /// Adds a specified number of directories to the total count.
-    pub fn add_dirs(&mut self, n: usize) {
-        self.dirs += n
+    pub(crate) fn add_dirs(&self, n: usize) {
+        self.dirs.fetch_add(n, Ordering::Relaxed);
     }
 
     /// Adds a specified number of files to the total count.
-    pub fn add_files(&mut self, n: usize) {
-        self.files += n
+    pub(crate) fn add_files(&self, n: usize) {
+        self.files.fetch_add(n, Ordering::Relaxed);
     }
 
     /// Adds a specified number of bytes to the total size.
-    pub fn add_byte_size(&mut self, n: u64) {
-        self.bytes += n;
+    pub(crate) fn add_byte_size(&self, n: u64) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Adds a specified number of unreadable entries to the total count.
+    pub(crate) fn add_errors(&self, n: usize) {
+        self.errors.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Adds a specified number of filtered entries to the total count.
+    pub(crate) fn add_filtered(&self, n: usize) {
+        self.filtered.fetch_add(n, Ordering::Relaxed);
     }
 }
 
@@ -34,7 +78,16 @@ impl std::fmt::Display for Statistics {
         write!(
             f,
             "{} directories, {} files ({} bytes)",
-            self.dirs, self.files, self.bytes
-        )
+            self.dirs(),
+            self.files(),
+            self.bytes()
+        )?;
+        if self.filtered() > 0 {
+            write!(f, ", {} filtered", self.filtered())?;
+        }
+        if self.errors() > 0 {
+            write!(f, ", {} errors", self.errors())?;
+        }
+        Ok(())
     }
 }
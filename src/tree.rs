@@ -1,37 +1,180 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::config::Config;
+use crate::diff::Diff;
+use crate::dupes::Duplicate;
 use crate::filter::FileFilter;
+use crate::git::{
+    ChangedFiles, Commit, GitEntry, GitState, GitStatus, GitStatuses, GitTree, History,
+};
+use crate::gitignore::IgnoreRules;
+use crate::metadata::{Metadata, NameCache};
+use crate::predicate::FileKind;
+use crate::search::ContentSearch;
+use crate::sort::{self, SortKey};
+use crate::source::{Source, Stat};
 use crate::stats::Statistics;
 
 /// Represents the type of a file system node
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[non_exhaustive]
 pub enum NodeType {
+    /// A regular file (or anything that is neither a directory nor a symbolic link)
     File,
+    /// A directory
     Directory,
+    /// A symbolic link, which is never followed
     SymbolicLink,
+    /// An entry whose type could not be determined, because it could not be read
+    Unknown,
 }
 
 /// Represents a node in the file system tree
 ///
 /// Each `TreeNode` contains information about a file or directory, including its name,
 /// path, type, size (if applicable), and its children nodes (if it is a directory).
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[non_exhaustive]
 pub struct TreeNode {
+    /// The file name of the entry (or the path itself, for a root without a file name)
     pub name: String,
+    /// The path of the entry, starting with the root of the tree
     pub path: PathBuf,
+    /// The type of the entry
     pub node_type: NodeType,
+    /// The size of the entry in bytes, if known
+    ///
+    /// For directories, this is the total size of the subtree (see `Config::measure_dirs`).
     pub size: Option<u64>,
+    /// The additional file system metadata, if collected (see `Config::metadata`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    /// The error that occurred while reading the entry, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The number of matches of the content search, if counted (see `Config::count_matches`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<usize>,
+    /// The git status of the entry, if collected and not clean (see `Config::git_status`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitStatus>,
+    /// The change of the file relative to a git revision, if listing changes
+    /// (see `Config::changed`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<GitState>,
+    /// The target of a symbolic link, if it could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+    /// The last commit that touched the entry, or the most recent one of the files in a
+    /// directory, if collected (see `Config::collect_history`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<Commit>,
+    /// The digest of the contents of the entry, in hexadecimal, if computed (see `Config::hash`)
+    ///
+    /// For directories, this is computed from the names, types and digests of their entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// How the entry differs between two trees, in a tree merged by `diff::diff`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<Diff>,
+    /// The group of files with identical contents that the file belongs to, in a tree
+    /// annotated by `Duplicates::annotate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate: Option<Duplicate>,
+    /// The entries of a directory, in display order
     pub children: Vec<TreeNode>,
+    /// The device and inode of a file with several hard links, so it is only counted once
+    #[serde(skip)]
+    pub(crate) hard_link: Option<(u64, u64)>,
+}
+
+impl TreeNode {
+    /// Replays this node and all of its descendants into a `Visitor`
+    ///
+    /// This produces the same stream of events as `TreeBuilder::walk` would have
+    /// produced for this tree.
+    pub fn walk<V: Visitor + ?Sized>(&self, visitor: &mut V) -> io::Result<()> {
+        self.visit(visitor, true)
+    }
+
+    fn visit<V: Visitor + ?Sized>(&self, visitor: &mut V, is_last: bool) -> io::Result<()> {
+        match self.node_type {
+            NodeType::Directory => {
+                visitor.enter_dir(self, is_last)?;
+                for (i, child) in self.children.iter().enumerate() {
+                    child.visit(visitor, i == self.children.len() - 1)?;
+                }
+                visitor.leave_dir(self)
+            }
+            NodeType::File | NodeType::SymbolicLink | NodeType::Unknown => {
+                visitor.entry(self, is_last)
+            }
+        }
+    }
+}
+
+/// Receives the entries of a tree as a stream of events, in display order
+///
+/// Every directory is reported by `enter_dir`, followed by the events of its children
+/// and a matching `leave_dir`. All other entries are reported by a single call to `entry`.
+/// The nodes passed to a visitor during a traversal do not carry their children.
+pub trait Visitor {
+    /// Called when a directory is entered
+    ///
+    /// `is_last`: True if the node is the last child of its parent (always true for the root)
+    fn enter_dir(&mut self, node: &TreeNode, is_last: bool) -> io::Result<()>;
+
+    /// Called for every entry that is not a directory
+    ///
+    /// `is_last`: True if the node is the last child of its parent (always true for the root)
+    fn entry(&mut self, node: &TreeNode, is_last: bool) -> io::Result<()>;
+
+    /// Called once all the children of a directory have been visited
+    fn leave_dir(&mut self, node: &TreeNode) -> io::Result<()>;
+}
+
+/// The entries of a directory that could be read
+#[derive(Default)]
+struct Listing {
+    /// The entries, each paired with the pending listing of its own entries if it is
+    /// a directory that will be descended into
+    entries: Vec<(TreeNode, Option<Pending>)>,
+    /// The error that stopped the directory from being read in full, if any
+    error: Option<io::Error>,
+}
+
+/// How many directory listings can be read ahead of the consumer at once
+///
+/// This bounds the memory used when the consumer is slow (e.g. a terminal or a pipe):
+/// once that many listings are waiting, further directories are only read when the
+/// consumer gets to them.
+const MAX_READ_AHEAD: usize = 256;
+
+/// A directory listing that is being read, or will be
+enum Pending {
+    /// Being read on the thread pool
+    Reading(mpsc::Receiver<io::Result<Listing>>),
+    /// Not read yet, because too many listings were already read ahead of the consumer
+    Deferred(PathBuf, Option<Arc<IgnoreRules>>),
+    /// Already received
+    Ready(Listing),
 }
 
 /// A builder for constructing a file system tree
 ///
 /// This builder walks a directory and constructs a `TreeNode` representation of the
-/// file system, based on the provided configuration.
+/// file system, based on the provided configuration. Directories are read ahead in
+/// parallel on a dedicated thread pool, while the entries are consumed in the order
+/// they were read, so the results are always deterministic.
+///
+/// The entries are read from the file system, or from a tree stored in a git commit
+/// (see `Source`).
 pub struct TreeBuilder<'a> {
     /// The configuration to use for the tree building process
     cfg: &'a Config,
@@ -39,10 +182,29 @@ pub struct TreeBuilder<'a> {
     root: std::path::PathBuf,
     /// The file filter used to determine which files and directories to include in the tree
     file_filter: FileFilter,
-    /// A set of visited paths to avoid processing the same path multiple times
-    visited: HashSet<PathBuf>,
+    /// The canonical paths of the directories already expanded, filled by the consumer in
+    /// display order so that a directory reached twice is always expanded at the same place
+    visited: Mutex<HashSet<PathBuf>>,
     /// The statistics collected during the tree building process
     stats: Statistics,
+    /// The thread pool used to read the directories in parallel
+    pool: rayon::ThreadPool,
+    /// Set when the consumer has stopped, so that pending reads can be skipped
+    cancelled: AtomicBool,
+    /// The number of listings being read, or read, that the consumer has not received yet
+    read_ahead: AtomicUsize,
+    /// The user and group names resolved while collecting metadata
+    names: NameCache,
+    /// The search that the contents of the files have to match, if any
+    search: Option<ContentSearch>,
+    /// The git status of the repository containing the root, if collected
+    git: Option<GitStatuses>,
+    /// The files changed relative to a git revision, if only those are shown
+    changes: Option<ChangedFiles>,
+    /// Where the entries are read from
+    source: Source,
+    /// The last commit that touched each file, if collected
+    history: Option<History>,
 }
 
 impl<'a> TreeBuilder<'a> {
@@ -53,7 +215,37 @@ impl<'a> TreeBuilder<'a> {
             cfg,
             file_filter: FileFilter::new(cfg)?,
             stats: Statistics::default(),
-            visited: HashSet::new(),
+            visited: Mutex::new(HashSet::new()),
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(cfg.threads.unwrap_or(0))
+                .build()?,
+            cancelled: AtomicBool::new(false),
+            read_ahead: AtomicUsize::new(0),
+            names: NameCache::default(),
+            search: cfg
+                .contains
+                .as_deref()
+                .map(|pattern| ContentSearch::new(pattern, cfg.binary))
+                .transpose()?,
+            git: if cfg.collect_git_status() {
+                GitStatuses::load(&cfg.root)?
+            } else {
+                None
+            },
+            changes: cfg
+                .changed
+                .as_deref()
+                .map(|rev| ChangedFiles::load(&cfg.root, rev, cfg.merge_base))
+                .transpose()?,
+            source: match &cfg.rev {
+                Some(rev) => Source::Git(Box::new(GitTree::load(&cfg.root, rev)?)),
+                None => Source::FileSystem,
+            },
+            history: if cfg.collect_history() {
+                History::load(&cfg.root, cfg.rev.as_deref())?
+            } else {
+                None
+            },
         })
     }
 
@@ -61,8 +253,282 @@ impl<'a> TreeBuilder<'a> {
     ///
     /// This method recursively walks the file system from the specified path and
     /// constructs a tree of `TreeNode` objects.
-    pub fn build(&mut self, path: &Path) -> std::io::Result<TreeNode> {
-        let metadata = std::fs::symlink_metadata(path)?;
+    pub fn build(&self, path: &Path) -> io::Result<TreeNode> {
+        let mut collector = TreeCollector {
+            cfg: self.cfg,
+            stack: Vec::new(),
+            root: None,
+        };
+        self.walk(path, &mut collector)?;
+        collector
+            .root
+            .ok_or_else(|| io::Error::other("the tree is empty"))
+    }
+
+    /// Walks the file system from the given path, streaming the entries into a `Visitor`
+    ///
+    /// Unlike `build`, this never holds the whole tree in memory. Each entry is handed
+    /// to the visitor as soon as it and all the entries before it have been read.
+    pub fn walk<V: Visitor + ?Sized>(&self, path: &Path, visitor: &mut V) -> io::Result<()> {
+        self.scoped(|scope| {
+            let (mut root, mut pending) = self.read_entry(scope, path, None)?;
+            if self.cfg.prune
+                && let Some(listing) = pending
+            {
+                let listing = self.prune(scope, self.receive(scope, &mut root, listing)?)?;
+                pending = Some(Pending::Ready(listing));
+            }
+            let (size, _) = self.emit(
+                scope,
+                root,
+                pending,
+                true,
+                Some(visitor),
+                &mut HashMap::new(),
+            )?;
+            self.stats.add_byte_size(size);
+            Ok(())
+        })
+    }
+
+    /// Returns a reference to the statistics collected during the tree build
+    pub fn get_stats(&self) -> &Statistics {
+        &self.stats
+    }
+
+    /// Consumes the builder, returning the statistics collected during the tree build
+    pub fn into_stats(self) -> Statistics {
+        self.stats
+    }
+
+    /// Runs a traversal in a scope of the thread pool
+    ///
+    /// The closure itself runs on the current thread, while the directory reads it
+    /// spawns run on the pool. If the traversal fails, the remaining reads are cancelled.
+    fn scoped<'s, T>(
+        &'s self,
+        op: impl FnOnce(&rayon::Scope<'s>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        self.cancelled.store(false, Ordering::Relaxed);
+        self.read_ahead.store(0, Ordering::Relaxed);
+        self.visited.lock().unwrap().clear();
+        self.pool.in_place_scope(|scope| {
+            let result = op(scope);
+            if result.is_err() {
+                self.cancelled.store(true, Ordering::Relaxed);
+            }
+            result
+        })
+    }
+
+    /// Hands a node, and then its pending children, to the visitor
+    ///
+    /// Entries below the maximum depth are only read to measure the size of their
+    /// ancestors, and are consumed without a visitor. Returns the number of bytes
+    /// the node adds to the size of its parent, and the digest of the node.
+    ///
+    /// `hard_links`: The size of each file with several hard links found so far in the
+    /// parent directory, by the ID of the file. A directory counts such a file once,
+    /// whichever of its links it contains, so the sizes do not depend on the sort order.
+    fn emit<'s, V: Visitor + ?Sized>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        mut node: TreeNode,
+        pending: Option<Pending>,
+        is_last: bool,
+        mut visitor: Option<&mut V>,
+        hard_links: &mut HashMap<(u64, u64), u64>,
+    ) -> io::Result<(u64, Option<String>)> {
+        match node.node_type {
+            NodeType::Directory => {
+                // Wait for the listing first, so that a read error can be shown on the directory
+                let listing = match pending {
+                    Some(pending) => self.receive(scope, &mut node, pending)?,
+                    None => Listing::default(),
+                };
+                if let Some(visitor) = visitor.as_deref_mut() {
+                    self.stats.add_dirs(1);
+                    visitor.enter_dir(&node, is_last)?;
+                }
+
+                let show_children = self.is_within_max_depth(&node.path);
+                let mut size = node.size.unwrap_or_default();
+                let mut links = HashMap::new();
+                let count = listing.entries.len();
+                let hash = self.cfg.hash_algorithm();
+                let mut digests = Vec::with_capacity(count);
+                for (i, (child, pending)) in listing.entries.into_iter().enumerate() {
+                    let visitor = visitor.as_deref_mut().filter(|_| show_children);
+                    let entry = hash.map(|_| (child.name.clone(), child.node_type.clone()));
+                    let (child_size, digest) =
+                        self.emit(scope, child, pending, i == count - 1, visitor, &mut links)?;
+                    size += child_size;
+                    if let Some((name, node_type)) = entry {
+                        digests.push((name, node_type, digest));
+                    }
+                }
+                if self.cfg.measure_dirs() {
+                    node.size = Some(size);
+                }
+                // The digest of a directory covers the entries that are listed, like its size
+                if let Some(algorithm) = hash
+                    && node.error.is_none()
+                {
+                    node.hash = Some(algorithm.hash_dir(digests));
+                }
+
+                if let Some(visitor) = visitor {
+                    visitor.leave_dir(&node)?;
+                }
+                // The files that are also linked from elsewhere in the parent are counted there
+                let mut added = size;
+                for (id, link_size) in links {
+                    if hard_links.insert(id, link_size).is_some() {
+                        added -= link_size;
+                    }
+                }
+                Ok((added, node.hash))
+            }
+            NodeType::File | NodeType::SymbolicLink | NodeType::Unknown => {
+                // Files with several hard links only count towards the size of a directory once
+                let size = node.size.unwrap_or_default();
+                let added = match node.hard_link {
+                    Some(id) if hard_links.insert(id, size).is_some() => 0,
+                    _ => size,
+                };
+                if let Some(visitor) = visitor {
+                    self.stats.add_files(1);
+                    visitor.entry(&node, is_last)?;
+                }
+                Ok((added, node.hash))
+            }
+        }
+    }
+
+    /// Waits for the pending listing of a directory to be read
+    ///
+    /// A deferred listing is read right away, on the current thread. If the directory
+    /// could not be read, or only in part, the error is recorded on it.
+    fn receive<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        dir: &mut TreeNode,
+        pending: Pending,
+    ) -> io::Result<Listing> {
+        let first_read = !matches!(pending, Pending::Ready(_));
+        let result = match pending {
+            Pending::Reading(rx) => {
+                let result = rx
+                    .recv()
+                    .map_err(|_| io::Error::other("directory read was cancelled"))?;
+                self.read_ahead.fetch_sub(1, Ordering::Relaxed);
+                result
+            }
+            Pending::Deferred(path, rules) => self.read_listing(scope, &path, rules),
+            Pending::Ready(listing) => Ok(listing),
+        };
+        let mut listing = match result {
+            Ok(listing) => listing,
+            Err(e) => Listing {
+                entries: Vec::new(),
+                error: Some(e),
+            },
+        };
+        if let Some(e) = listing.error.take() {
+            self.record_error(dir, e)?;
+        }
+        if first_read && !self.first_visit(dir)? {
+            return Ok(Listing::default());
+        }
+        Ok(listing)
+    }
+
+    /// Checks whether a directory is expanded for the first time, and tracks that it is
+    ///
+    /// This runs on the consumer side, in display order, rather than on the threads that
+    /// read ahead, so that which of two paths to the same directory is expanded does not
+    /// depend on which read finished first.
+    fn first_visit(&self, dir: &mut TreeNode) -> io::Result<bool> {
+        // A git tree cannot contain cycles
+        if let Source::Git(_) = self.source {
+            return Ok(true);
+        }
+        match dir.path.canonicalize() {
+            Ok(path) => Ok(self.visited.lock().unwrap().insert(path)),
+            Err(e) => {
+                self.record_error(dir, e)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Removes the directories that contain no files, at any depth, from a listing
+    ///
+    /// This has to wait for the listings of all the subdirectories, which are handed back
+    /// as pending listings that are ready. Directories that could not be read are kept,
+    /// so that the error is shown.
+    fn prune<'s>(&'s self, scope: &rayon::Scope<'s>, listing: Listing) -> io::Result<Listing> {
+        let mut pruned = Vec::with_capacity(listing.entries.len());
+        for (mut node, pending) in listing.entries {
+            if matches!(node.node_type, NodeType::Directory) {
+                let children = match pending {
+                    Some(pending) => self.prune(scope, self.receive(scope, &mut node, pending)?)?,
+                    None => Listing::default(),
+                };
+                if children.entries.is_empty() && node.error.is_none() {
+                    continue;
+                }
+                pruned.push((node, Some(Pending::Ready(children))));
+            } else {
+                pruned.push((node, pending));
+            }
+        }
+        Ok(Listing {
+            entries: pruned,
+            error: listing.error,
+        })
+    }
+
+    /// Records an error on a node, or returns it if running in strict mode
+    fn record_error(&self, node: &mut TreeNode, error: io::Error) -> io::Result<()> {
+        if self.cfg.strict {
+            let message = format!("{}: {error}", node.path.display());
+            return Err(io::Error::new(error.kind(), message));
+        }
+        self.stats.add_errors(1);
+        node.error = Some(describe_error(&error));
+        Ok(())
+    }
+
+    /// Creates the `TreeNode` for a path, and starts reading its entries if it is
+    /// a directory that should be descended into
+    ///
+    /// `rules`: The ignore rules of the parent directory (`None` for the root)
+    fn read_entry<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        path: &Path,
+        rules: Option<&Arc<IgnoreRules>>,
+    ) -> io::Result<(TreeNode, Option<Pending>)> {
+        let mut node = match &self.source {
+            Source::FileSystem => self.build_node(path)?,
+            Source::Git(tree) => self.build_git_node(tree, path),
+        };
+        self.hash_contents(&mut node)?;
+        let pending = self
+            .should_descend(&node)
+            .then(|| self.read_dir(scope, node.path.clone(), rules.cloned()));
+        Ok((node, pending))
+    }
+
+    /// Builds the `TreeNode` for a single path, without its children
+    fn build_node(&self, path: &Path) -> io::Result<TreeNode> {
+        let name = node_name(path);
+
+        let metadata = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) => return self.error_node(name, path, e),
+        };
 
         let file_type = metadata.file_type();
         let node_type = if file_type.is_dir() {
@@ -73,69 +539,404 @@ impl<'a> TreeBuilder<'a> {
             NodeType::File
         };
 
+        // A directory starts out with its own size, to which the size of its entries is
+        // added as they are consumed (its own size only counts when measuring disk usage)
         let size = if !metadata.is_dir() {
-            Some(metadata.len())
+            Some(self.usage(&metadata))
+        } else if self.cfg.measure_dirs() {
+            Some(if self.cfg.disk_usage {
+                self.usage(&metadata)
+            } else {
+                0
+            })
         } else {
             None
         };
 
-        let name = path
-            .file_name()
-            .map(|n| n.to_string_lossy().to_string())
-            .unwrap_or_else(|| path.display().to_string());
-
-        let mut node = TreeNode {
+        Ok(TreeNode {
             name,
             path: path.to_path_buf(),
             node_type,
             size,
+            metadata: self
+                .cfg
+                .collect_metadata()
+                .then(|| Metadata::collect(&metadata, &self.names)),
+            error: None,
+            matches: None,
+            git: self
+                .git
+                .as_ref()
+                .and_then(|git| git.get(self.relative(path), metadata.is_dir())),
+            change: self
+                .changes
+                .as_ref()
+                .and_then(|changes| changes.get(self.relative(path))),
+            target: file_type
+                .is_symlink()
+                .then(|| std::fs::read_link(path).ok())
+                .flatten(),
+            commit: self.last_commit(path, metadata.is_dir()),
+            hash: None,
+            diff: None,
+            duplicate: None,
             children: Vec::new(),
+            hard_link: hard_link(&metadata),
+        })
+    }
+
+    /// Builds the `TreeNode` for an entry of a git tree, without its children
+    fn build_git_node(&self, tree: &GitTree, path: &Path) -> TreeNode {
+        // The root of the tree has no entry of its own
+        let entry = tree.entry(self.relative(path));
+        let node_type = match entry.and_then(Stat::kind) {
+            Some(FileKind::Symlink) => NodeType::SymbolicLink,
+            Some(FileKind::File) => NodeType::File,
+            _ => NodeType::Directory,
+        };
+        let is_dir = matches!(node_type, NodeType::Directory);
+        let size = if is_dir {
+            self.cfg.measure_dirs().then_some(0)
+        } else {
+            entry.map(Stat::size)
         };
 
-        match node.node_type {
-            NodeType::Directory => self.process_directory(path, &mut node)?,
-            NodeType::File | NodeType::SymbolicLink => {
-                self.stats.add_files(1);
-                if let Some(size) = size {
-                    self.stats.add_byte_size(size);
+        TreeNode {
+            name: node_name(path),
+            path: path.to_path_buf(),
+            node_type,
+            size,
+            metadata: self.cfg.collect_metadata().then(|| Metadata {
+                mode: entry.map_or(Some(0o755), Stat::mode),
+                ..Default::default()
+            }),
+            error: None,
+            matches: None,
+            git: None,
+            change: None,
+            target: entry.and_then(GitEntry::target).map(Path::to_path_buf),
+            commit: self.last_commit(path, is_dir),
+            hash: None,
+            diff: None,
+            duplicate: None,
+            children: Vec::new(),
+            hard_link: None,
+        }
+    }
+
+    /// Returns the last commit that touched an entry, if collected
+    fn last_commit(&self, path: &Path, is_dir: bool) -> Option<Commit> {
+        let history = self.history.as_ref()?;
+        history.get(self.relative(path), is_dir).cloned()
+    }
+
+    /// Returns the size of an entry, either apparent or on disk depending on the configuration
+    fn usage(&self, metadata: &std::fs::Metadata) -> u64 {
+        #[cfg(unix)]
+        if self.cfg.disk_usage {
+            use std::os::unix::fs::MetadataExt;
+            return metadata.blocks() * 512;
+        }
+        metadata.len()
+    }
+
+    /// Builds a `TreeNode` for an entry that could not be read
+    fn error_node(&self, name: String, path: &Path, error: io::Error) -> io::Result<TreeNode> {
+        let mut node = TreeNode {
+            name,
+            path: path.to_path_buf(),
+            node_type: NodeType::Unknown,
+            size: None,
+            metadata: None,
+            error: None,
+            matches: None,
+            git: None,
+            change: None,
+            target: None,
+            commit: None,
+            hash: None,
+            diff: None,
+            duplicate: None,
+            children: Vec::new(),
+            hard_link: None,
+        };
+        self.record_error(&mut node, error)?;
+        Ok(node)
+    }
+
+    /// Checks whether the entries of a node should be read
+    ///
+    /// When measuring, pruning or hashing directories, the entries below the maximum depth
+    /// are read too, so that the sizes and digests of the directories that are shown are
+    /// complete, and so that directories with files below the maximum depth are not pruned.
+    fn should_descend(&self, node: &TreeNode) -> bool {
+        let read_below_max_depth =
+            self.cfg.measure_dirs() || self.cfg.prune || self.cfg.hash_algorithm().is_some();
+        matches!(node.node_type, NodeType::Directory)
+            && (self.is_within_max_depth(&node.path) || read_below_max_depth)
+    }
+
+    /// Starts reading the entries of a directory on the thread pool
+    ///
+    /// Every subdirectory found is in turn read as soon as possible, so the tree is read
+    /// ahead of the consumer, up to `MAX_READ_AHEAD` listings. Beyond that, the read is
+    /// deferred until the consumer needs the listing.
+    fn read_dir<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        path: PathBuf,
+        parent_rules: Option<Arc<IgnoreRules>>,
+    ) -> Pending {
+        if self.read_ahead.fetch_add(1, Ordering::Relaxed) >= MAX_READ_AHEAD {
+            self.read_ahead.fetch_sub(1, Ordering::Relaxed);
+            return Pending::Deferred(path, parent_rules);
+        }
+        let (tx, rx) = mpsc::sync_channel(1);
+        scope.spawn(move |scope| {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            // The consumer may have stopped already, in which case nobody is listening
+            let _ = tx.send(self.read_listing(scope, &path, parent_rules));
+        });
+        Pending::Reading(rx)
+    }
+
+    /// Reads the sorted entries of a directory
+    fn read_listing<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        path: &Path,
+        parent_rules: Option<Arc<IgnoreRules>>,
+    ) -> io::Result<Listing> {
+        let mut listing = match &self.source {
+            Source::FileSystem => self.read_fs_dir(scope, path, parent_rules)?,
+            Source::Git(tree) => self.read_git_dir(scope, tree, path)?,
+        };
+        listing
+            .entries
+            .sort_by(|(a, _), (b, _)| sort::compare(a, b, self.cfg));
+        Ok(listing)
+    }
+
+    /// Reads the filtered entries of a directory on disk
+    fn read_fs_dir<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        path: &Path,
+        parent_rules: Option<Arc<IgnoreRules>>,
+    ) -> io::Result<Listing> {
+        let rules = self.file_filter.ignore_rules(path, parent_rules.as_ref());
+        let entries = self.file_filter.filter_entries(path, &rules, &self.stats)?;
+        let mut listing = Listing::default();
+        for entry in entries {
+            // An entry that cannot be read ends the listing, so the error belongs to the
+            // directory rather than to an entry of its own
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    listing.error = Some(e);
+                    break;
                 }
+            };
+            if !self.is_changed(&entry.path()) {
+                self.stats.add_filtered(1);
+                continue;
             }
+            let (mut node, pending) = self.read_entry(scope, &entry.path(), Some(&rules))?;
+            if !self.search_contents(&mut node)? {
+                self.stats.add_filtered(1);
+                continue;
+            }
+            listing.entries.push((node, pending));
         }
+        self.add_missing_entries(path, &mut listing);
+        Ok(listing)
+    }
 
-        Ok(node)
+    /// Reads the filtered entries of a directory of a git tree
+    fn read_git_dir<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        tree: &GitTree,
+        path: &Path,
+    ) -> io::Result<Listing> {
+        let relative_dir = self.relative(path);
+        let mut listing = Listing::default();
+        for name in tree.entries(relative_dir) {
+            let relative = relative_dir.join(name);
+            let Some(entry) = tree.entry(&relative) else {
+                continue;
+            };
+            if !self
+                .file_filter
+                .should_include_path(&relative, entry.is_dir(), Some(entry))
+            {
+                self.stats.add_filtered(1);
+                continue;
+            }
+
+            let (mut node, pending) = self.read_entry(scope, &path.join(name), None)?;
+            if !self.search_contents(&mut node)? {
+                self.stats.add_filtered(1);
+                continue;
+            }
+            listing.entries.push((node, pending));
+        }
+        Ok(listing)
     }
 
-    /// Returns a reference to the statistics collected during the tree build
-    pub fn get_stats(&self) -> &Statistics {
-        &self.stats
+    /// Returns the path of an entry relative to the root
+    fn relative<'p>(&self, path: &'p Path) -> &'p Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// Checks whether an entry has changed, or leads to a change, if only the changed
+    /// files are shown
+    fn is_changed(&self, path: &Path) -> bool {
+        self.changes
+            .as_ref()
+            .is_none_or(|changes| changes.contains(self.relative(path)))
     }
 
-    /// Processes a directory, reading its entries and recursively building the tree
-    fn process_directory(&mut self, path: &Path, node: &mut TreeNode) -> std::io::Result<()> {
-        // Check to see if we have already visited this directory (e.g. cyclic symlink)
-        let canonical_path = path.canonicalize()?;
-        if self.visited.contains(&canonical_path) {
-            return Ok(()); // if we have, then skip processing it again
+    /// Adds the changed entries of a directory that do not exist on disk to its listing,
+    /// i.e. the deleted files and the directories that contained them
+    fn add_missing_entries(&self, dir: &Path, listing: &mut Listing) {
+        let Some(changes) = &self.changes else {
+            return;
+        };
+        let relative_dir = self.relative(dir);
+        for name in changes.entries(relative_dir) {
+            let path = dir.join(name);
+            let relative = relative_dir.join(name);
+            let is_dir = changes.is_dir(&relative);
+            if std::fs::symlink_metadata(&path).is_ok()
+                || !self
+                    .file_filter
+                    .should_include_path(&relative, is_dir, None)
+            {
+                continue;
+            }
+
+            let node = self.missing_node(path, changes.get(&relative), is_dir);
+            let pending = is_dir.then(|| {
+                let mut children = Listing::default();
+                self.add_missing_entries(&node.path, &mut children);
+                children
+                    .entries
+                    .sort_by(|(a, _), (b, _)| sort::compare(a, b, self.cfg));
+                Pending::Ready(children)
+            });
+            listing.entries.push((node, pending));
         }
-        self.visited.insert(canonical_path); // Track that we've visited this path
+    }
 
-        self.stats.add_dirs(1);
+    /// Builds the `TreeNode` for an entry that does not exist on disk
+    fn missing_node(&self, path: PathBuf, change: Option<GitState>, is_dir: bool) -> TreeNode {
+        TreeNode {
+            name: node_name(&path),
+            path,
+            node_type: if is_dir {
+                NodeType::Directory
+            } else {
+                NodeType::File
+            },
+            size: (is_dir && self.cfg.measure_dirs()).then_some(0),
+            metadata: None,
+            error: None,
+            matches: None,
+            git: None,
+            change,
+            target: None,
+            commit: None,
+            hash: None,
+            diff: None,
+            duplicate: None,
+            children: Vec::new(),
+            hard_link: None,
+        }
+    }
 
-        // If we are still within the specified max-depth, keep recursing
-        if self.is_within_max_depth(path) {
-            node.children = self.read_dir(path)?;
+    /// Checks whether a node passes the content search, if any, and records its match count
+    ///
+    /// Directories and entries that could not be read always pass, while the other entries
+    /// that are not regular files never do. A file that cannot be searched is kept, with
+    /// the error.
+    fn search_contents(&self, node: &mut TreeNode) -> io::Result<bool> {
+        let Some(search) = &self.search else {
+            return Ok(true);
+        };
+        match node.node_type {
+            NodeType::Directory | NodeType::Unknown => return Ok(true),
+            NodeType::SymbolicLink => return Ok(false),
+            NodeType::File => {}
         }
 
-        Ok(())
+        let count = self.cfg.count_matches;
+        let found = match &self.source {
+            Source::FileSystem => {
+                // Opening a named pipe would block until something writes to it
+                if !std::fs::symlink_metadata(&node.path).is_ok_and(|m| m.is_file()) {
+                    return Ok(false);
+                }
+                search.search(&node.path, count)
+            }
+            Source::Git(tree) => tree
+                .read(self.relative(&node.path))
+                .and_then(|contents| search.search_reader(contents.as_slice(), count)),
+        };
+        match found {
+            Ok(Some(matches)) if matches > 0 => {
+                if self.cfg.count_matches {
+                    node.matches = Some(matches);
+                }
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(e) => {
+                self.record_error(node, e)?;
+                Ok(true)
+            }
+        }
     }
 
-    /// Reads the entries of a directory and builds a vector of `TreeNode` children
-    fn read_dir(&mut self, path: &Path) -> std::io::Result<Vec<TreeNode>> {
-        self.file_filter
-            .filter_entries(path)?
-            .into_iter()
-            .map(|entry| self.build(&entry.path()))
-            .collect()
+    /// Computes the digest of the contents of a regular file, if requested
+    ///
+    /// The digest of a symbolic link is that of its target path, since links are never
+    /// followed. A file that cannot be read is kept, with the error.
+    fn hash_contents(&self, node: &mut TreeNode) -> io::Result<()> {
+        let Some(algorithm) = self.cfg.hash_algorithm() else {
+            return Ok(());
+        };
+        if node.error.is_some() {
+            return Ok(());
+        }
+        match (&node.node_type, &node.target) {
+            (NodeType::File, _) => {}
+            (NodeType::SymbolicLink, Some(target)) => {
+                node.hash = Some(algorithm.hash_bytes(target.as_os_str().as_encoded_bytes()));
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+
+        let digest = match &self.source {
+            Source::FileSystem => {
+                // Opening a named pipe would block until something writes to it
+                if !std::fs::symlink_metadata(&node.path).is_ok_and(|m| m.is_file()) {
+                    return Ok(());
+                }
+                algorithm.hash_file(&node.path)
+            }
+            Source::Git(tree) => tree
+                .read(self.relative(&node.path))
+                .and_then(|contents| algorithm.hash_reader(contents.as_slice())),
+        };
+        match digest {
+            Ok(digest) => node.hash = Some(digest),
+            Err(e) => self.record_error(node, e)?,
+        }
+        Ok(())
     }
 
     /// Checks if the current path is within the configured maximum depth
@@ -153,3 +954,232 @@ impl<'a> TreeBuilder<'a> {
         current_depth < max_depth
     }
 }
+
+/// A `Visitor` that assembles the visited entries back into a `TreeNode`
+struct TreeCollector<'a> {
+    /// The configuration, used to sort the entries once the directory sizes are known
+    cfg: &'a Config,
+    /// The directories currently entered, with the children collected so far
+    stack: Vec<TreeNode>,
+    /// The root node, once it has been collected
+    root: Option<TreeNode>,
+}
+
+impl TreeCollector<'_> {
+    /// Adds a complete node to the directory currently entered, or makes it the root
+    fn add(&mut self, node: TreeNode) {
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.root = Some(node),
+        }
+    }
+}
+
+impl Visitor for TreeCollector<'_> {
+    fn enter_dir(&mut self, node: &TreeNode, _is_last: bool) -> io::Result<()> {
+        self.stack.push(node.clone());
+        Ok(())
+    }
+
+    fn entry(&mut self, node: &TreeNode, _is_last: bool) -> io::Result<()> {
+        self.add(node.clone());
+        Ok(())
+    }
+
+    fn leave_dir(&mut self, node: &TreeNode) -> io::Result<()> {
+        let mut dir = self.stack.pop().expect("leave_dir without enter_dir");
+        dir.size = node.size;
+        dir.hash = node.hash.clone();
+        // The sizes of the subdirectories were not known when the directory was read
+        if self.cfg.sort == SortKey::Size {
+            dir.children.sort_by(|a, b| sort::compare(a, b, self.cfg));
+        }
+        self.add(dir);
+        Ok(())
+    }
+}
+
+/// Returns the name shown for the entry at a path, which is the whole path for the root
+fn node_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Returns the device and inode of a file with several hard links
+#[cfg(unix)]
+fn hard_link(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (!metadata.is_dir() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+/// Returns the device and inode of a file with several hard links
+#[cfg(not(unix))]
+fn hard_link(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Describes an I/O error with a short message, such as "permission denied"
+fn describe_error(error: &io::Error) -> String {
+    let message = error.to_string();
+    // Drop the OS error code, e.g. "Permission denied (os error 13)"
+    let message = match message.find(" (os error") {
+        Some(i) => &message[..i],
+        None => &message,
+    };
+    let mut chars = message.chars();
+    chars
+        .next()
+        .map(|first| first.to_lowercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use crate::hash::HashAlgorithm;
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_count_once_in_each_directory_whatever_the_sort() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b"] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        std::fs::write(dir.path().join("a/file"), "0123456789").unwrap();
+        std::fs::hard_link(dir.path().join("a/file"), dir.path().join("b/link")).unwrap();
+        std::fs::hard_link(dir.path().join("a/file"), dir.path().join("b/other")).unwrap();
+
+        for sort in [SortKey::Name, SortKey::Size] {
+            let cfg = ConfigBuilder {
+                root: Some(dir.path().to_path_buf()),
+                size: true,
+                sort: Some(sort),
+                ..Default::default()
+            }
+            .build();
+            let (tree, stats) = crate::build(&cfg).unwrap();
+            let size = |name: &str| {
+                let child = tree.children.iter().find(|c| c.name == name).unwrap();
+                child.size
+            };
+            assert_eq!(size("a"), Some(10), "{sort:?}");
+            assert_eq!(size("b"), Some(10), "{sort:?}");
+            assert_eq!(tree.size, Some(10), "{sort:?}");
+            assert_eq!(stats.bytes(), 10, "{sort:?}");
+        }
+    }
+
+    #[test]
+    fn an_empty_directory_and_an_empty_file_have_different_digests() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("p/x")).unwrap();
+        std::fs::create_dir(dir.path().join("q")).unwrap();
+        std::fs::write(dir.path().join("q/x"), "").unwrap();
+
+        let cfg = ConfigBuilder {
+            root: Some(dir.path().to_path_buf()),
+            hash: Some(HashAlgorithm::Blake3),
+            ..Default::default()
+        }
+        .build();
+        let (tree, _) = crate::build(&cfg).unwrap();
+        let digest = |name: &str| {
+            let child = tree.children.iter().find(|c| c.name == name).unwrap();
+            child.hash.clone().unwrap()
+        };
+        assert_ne!(digest("p"), digest("q"));
+    }
+
+    #[test]
+    fn parallel_output_matches_a_single_thread() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            let sub = dir.path().join(format!("d{i}/e{}", i % 3));
+            std::fs::create_dir_all(&sub).unwrap();
+            std::fs::write(sub.join("file"), "x".repeat(i)).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("d1"), dir.path().join("d0/back")).unwrap();
+
+        let render = |threads| {
+            let cfg = ConfigBuilder {
+                root: Some(dir.path().to_path_buf()),
+                size: true,
+                hash: Some(HashAlgorithm::Blake3),
+                threads: Some(threads),
+                ..Default::default()
+            }
+            .build();
+            let (tree, _) = crate::build(&cfg).unwrap();
+            serde_json::to_string(&tree).unwrap()
+        };
+        let expected = render(1);
+        for _ in 0..5 {
+            assert_eq!(render(8), expected);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_entries_are_shown_unless_strict() {
+        // Unlike permissions, a path longer than the system limit cannot be read even by root
+        let dir = tempfile::tempdir().unwrap();
+        let segment = "d".repeat(200);
+        let deep = || std::iter::repeat_n(segment.as_str(), 12).collect::<PathBuf>();
+        std::fs::create_dir_all(dir.path().join("a").join(deep())).unwrap();
+        std::fs::create_dir_all(dir.path().join("b").join(deep())).unwrap();
+        let moved = dir.path().join("b").join(deep()).join("a");
+        std::fs::rename(dir.path().join("a"), moved).unwrap();
+
+        let build = |strict| {
+            let cfg = ConfigBuilder {
+                root: Some(dir.path().to_path_buf()),
+                strict,
+                ..Default::default()
+            }
+            .build();
+            crate::build(&cfg)
+        };
+
+        fn has_error(node: &TreeNode) -> bool {
+            node.error.is_some() || node.children.iter().any(has_error)
+        }
+        let (tree, stats) = build(false).unwrap();
+        assert!(has_error(&tree));
+        assert!(stats.errors() > 0);
+
+        let Err(error) = build(true) else {
+            panic!("a strict build should fail");
+        };
+        let error = error.to_string();
+        assert!(error.contains(&segment), "{error}");
+    }
+
+    #[test]
+    fn prune_drops_the_directories_left_empty_by_the_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["docs/api", "src/cli", "empty"] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        std::fs::write(dir.path().join("docs/api/index.md"), "").unwrap();
+        std::fs::write(dir.path().join("src/cli/args.rs"), "").unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+
+        let cfg = ConfigBuilder {
+            root: Some(dir.path().to_path_buf()),
+            include: Some(vec!["*.rs".to_string()]),
+            prune: true,
+            ..Default::default()
+        }
+        .build();
+        let (tree, _) = crate::build(&cfg).unwrap();
+        let names = |node: &TreeNode| -> Vec<String> {
+            node.children.iter().map(|c| c.name.clone()).collect()
+        };
+        assert_eq!(names(&tree), ["src"]);
+        assert_eq!(names(&tree.children[0]), ["cli", "lib.rs"]);
+        assert_eq!(names(&tree.children[0].children[0]), ["args.rs"]);
+    }
+}